@@ -1,21 +1,45 @@
+mod console;
+mod drm;
+mod tty;
+mod wayland;
+mod x11;
+
 use crate::{
+    config::{Configuration, Fade, FADE_INTERVAL},
     state::{self},
-    utils::temp_to_gamma,
 };
 use anyhow::Result;
 use bluegone::StateFileName;
-use serde::Deserialize;
-use x11rb::connection::Connection;
-use x11rb::protocol::randr::*;
-use x11rb::rust_connection::RustConnection;
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, str::FromStr, time::Duration};
 
 pub type GammaValue = Vec<u16>;
+
+/// What the backends connect to, taken from the command line or the configuration.
+/// Left empty the backend picks one on its own.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BackendOptions {
+    /// Displays to connect to, e.g. `:0` and `:1` for X11. Empty when the environment decides.
+    pub displays: Vec<String>,
+    /// Virtual console the console backend opens, e.g. `/dev/tty2`
+    pub console: Option<PathBuf>,
+    /// Card the drm backend opens, e.g. `/dev/dri/card1`
+    pub drm_card: Option<PathBuf>,
+}
+
+/// Callback of `GammaBackend::watch_outputs`
+pub type OutputsChanged = std::sync::Arc<dyn Fn(Vec<u32>) + Send + Sync>;
 // pub type Temperature = f64;
 
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
 pub struct Temperature(f64);
 
 impl Temperature {
+    /// Range in Kelvin the color algorithms are valid for
+    pub const RANGE: std::ops::RangeInclusive<f64> = 1000.0..=40000.0;
+    /// Temperature that leaves colors untouched
+    pub const NEUTRAL: f64 = 6500.0;
+
     pub fn new(value: f64) -> Self {
         Self(value)
     }
@@ -31,114 +55,419 @@ impl std::fmt::Display for Temperature {
     }
 }
 
+impl TryFrom<String> for Temperature {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Ok(Temperature(value.trim().parse()?))
+    }
+}
+
 impl StateFileName for Temperature {
     fn name() -> String {
         "temperature".into()
     }
 }
 
-#[derive(Debug, Default, clap::ValueEnum, Clone)]
-pub enum Backend {
-    Tty,
-    #[default]
-    X11,
+/// Factor every channel of the gamma ramp is scaled by, between 0.1 and 1.0
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "f64")]
+pub struct Brightness(f64);
+
+impl Brightness {
+    pub const RANGE: std::ops::RangeInclusive<f64> = 0.1..=1.0;
+
+    pub fn as_f64(&self) -> f64 {
+        self.0
+    }
 }
 
-impl<'a> Deserialize<'a> for Backend {
-    fn deserialize<D>(deserializer: D) -> Result<Backend, D::Error>
-    where
-        D: serde::Deserializer<'a>,
-    {
-        let s = String::deserialize(deserializer)?;
-        Backend::try_from(s.as_str()).map_err(serde::de::Error::custom)
+impl Default for Brightness {
+    fn default() -> Self {
+        Self(1.0)
     }
 }
 
-impl TryFrom<&str> for Backend {
+impl TryFrom<f64> for Brightness {
+    type Error = String;
+
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        match Self::RANGE.contains(&value) {
+            true => Ok(Self(value)),
+            false => Err(format!(
+                "Brightness must be between 0.1 and 1.0, got {value}"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Brightness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl TryFrom<String> for Brightness {
     type Error = anyhow::Error;
 
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value.to_uppercase().as_str() {
-            "TTY" => Ok(Backend::Tty),
-            "X11" => Ok(Backend::X11),
-            _ => anyhow::bail!("Invalid backend"),
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Brightness::try_from(value.trim().parse::<f64>()?).map_err(anyhow::Error::msg)
+    }
+}
+
+impl StateFileName for Brightness {
+    fn name() -> String {
+        "brightness".into()
+    }
+}
+
+/// Exponent applied to the red, green and blue ramps, like the `gamma` option of redshift.
+/// Values below 1.0 darken a channel, values above brighten it.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "[f64; 3]")]
+pub struct GammaCorrection([f64; 3]);
+
+impl GammaCorrection {
+    pub const RANGE: std::ops::RangeInclusive<f64> = 0.1..=10.0;
+
+    pub fn channels(&self) -> [f64; 3] {
+        self.0
+    }
+}
+
+impl Default for GammaCorrection {
+    fn default() -> Self {
+        Self([1.0; 3])
+    }
+}
+
+impl TryFrom<[f64; 3]> for GammaCorrection {
+    type Error = String;
+
+    fn try_from(value: [f64; 3]) -> Result<Self, Self::Error> {
+        match value.iter().all(|channel| Self::RANGE.contains(channel)) {
+            true => Ok(Self(value)),
+            false => Err(format!(
+                "Gamma must be between 0.1 and 10.0 for every channel, got {value:?}"
+            )),
         }
     }
 }
 
-impl Backend {
-    pub fn set_gamma(&self, gamma_r: f64, gamma_g: f64, gamma_b: f64) -> Result<()> {
-        match self {
-            Backend::Tty => set_gamma_for_tty(gamma_r, gamma_g, gamma_b),
-            Backend::X11 => set_gamma_for_x11(gamma_r, gamma_g, gamma_b),
+impl FromStr for GammaCorrection {
+    type Err = String;
+
+    /// Parses either one value for all channels or `red:green:blue`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let channels = value
+            .trim()
+            .split(':')
+            .map(|channel| channel.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| format!("`{value}` is not a gamma value"))?;
+
+        match channels[..] {
+            [all] => Self::try_from([all; 3]),
+            [r, g, b] => Self::try_from([r, g, b]),
+            _ => Err(format!(
+                "Expected one value or red:green:blue, got `{value}`"
+            )),
         }
     }
+}
 
-    pub fn set_temperature(&self, temp: Temperature) -> Result<()> {
-        state::write(temp)?;
-        let gamma = temp_to_gamma(temp.as_f64());
-        self.set_gamma(gamma.0, gamma.1, gamma.2)
+impl std::fmt::Display for GammaCorrection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [r, g, b] = self.0;
+        write!(f, "{r}:{g}:{b}")
     }
 }
 
-pub fn set_gamma_for_x11(gamma_r: f64, gamma_g: f64, gamma_b: f64) -> Result<()> {
-    let (conn, _) = RustConnection::connect(None)?;
+impl TryFrom<String> for GammaCorrection {
+    type Error = anyhow::Error;
 
-    let screen = &conn.setup().roots[0];
-    let res = conn
-        .randr_get_screen_resources_current(screen.root)?
-        .reply()?;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse().map_err(anyhow::Error::msg)
+    }
+}
 
-    for &crtc in &res.crtcs {
-        let size = conn.randr_get_crtc_gamma_size(crtc)?.reply()?.size as usize;
+impl StateFileName for GammaCorrection {
+    fn name() -> String {
+        "gamma".into()
+    }
+}
 
-        let start = 0_u16;
-        let mut gamma = Gamma {
-            red: vec![start; size],
-            green: vec![start; size],
-            blue: vec![start; size],
-        };
+/// Stores the brightness and gamma correction applied from now on, `None` keeps the current one
+pub fn write_correction(
+    displays: &[String],
+    brightness: Option<Brightness>,
+    gamma: Option<GammaCorrection>,
+) -> Result<()> {
+    if let Some(brightness) = brightness {
+        state::write(displays, brightness)?;
+    }
+    if let Some(gamma) = gamma {
+        state::write(displays, gamma)?;
+    }
+    Ok(())
+}
 
-        for i in 0..size {
-            let g = 65535.0 * (i as f64) / (size as f64);
-            gamma.red[i] = (g * gamma_r) as u16;
-            gamma.green[i] = (g * gamma_g) as u16;
-            gamma.blue[i] = (g * gamma_b) as u16;
+/// A display that can be targeted by a backend, e.g. a RandR output or a Wayland output.
+#[derive(Debug, Clone, Serialize)]
+pub struct Output {
+    /// Id of whatever the gamma ramp is applied to, such as a CRTC for DRM.
+    /// `None` when the output is disabled.
+    pub id: Option<u32>,
+    pub name: String,
+    pub connected: bool,
+    /// CRTC the output is shown on, for backends that drive CRTCs
+    pub crtc: Option<u32>,
+}
+
+impl Output {
+    pub fn id(&self) -> Result<u32> {
+        match self.id {
+            Some(id) => Ok(id),
+            None => anyhow::bail!("Output {} is disabled", self.name),
         }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Capabilities {
+    /// Outputs can be given different gamma ramps
+    pub per_output: bool,
+    /// Gamma stays applied after the process exits
+    pub persistent: bool,
+}
 
-        conn.randr_set_crtc_gamma(crtc, &gamma.red, &gamma.green, &gamma.blue)?;
+pub trait GammaBackend {
+    fn connect(&mut self) -> Result<()>;
+    fn outputs(&mut self) -> Result<Vec<Output>>;
+    fn ramp_size(&mut self, output: &Output) -> Result<usize>;
+    fn set_ramp(&mut self, output: &Output, gamma: &Gamma) -> Result<()>;
+    /// Resets every output to the gamma it had before we touched it.
+    fn restore(&mut self) -> Result<()>;
+    fn capabilities(&self) -> Capabilities;
+
+    /// Sends any pending requests to the display server.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
     }
 
-    conn.flush()?;
-    Ok(())
+    /// Sets a linear ramp on every enabled output, leaving all colors untouched.
+    fn reset(&mut self) -> Result<()> {
+        for output in self.outputs()? {
+            if output.id.is_none() {
+                continue;
+            }
+
+            let size = self.ramp_size(&output)?;
+            let gamma = Gamma::new(size, 1.0, 1.0, 1.0, &GammaCorrection::default());
+            self.set_ramp(&output, &gamma)?;
+        }
+
+        self.flush()
+    }
+
+    /// Gamma ramp currently applied to the output, `None` if the backend can't read it back.
+    fn current_ramp(&mut self, _output: &Output) -> Result<Option<Gamma>> {
+        Ok(None)
+    }
+
+    /// Calls `changed` from another thread with the ids of outputs that were added or
+    /// reconfigured, e.g. a monitor that was plugged in and got a fresh ramp.
+    /// Backends that can't tell never call it.
+    fn watch_outputs(&mut self, _changed: OutputsChanged) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_temperature(
+        &mut self,
+        temp: Temperature,
+        config: &Configuration,
+        displays: &[String],
+    ) -> Result<()> {
+        state::write(displays, temp)?;
+        self.apply_temperature(temp, config, displays, None)
+    }
+
+    /// Gradually moves from the last applied temperature to `temp`. `wait` is called between
+    /// every step and cancels the fade by returning false, in which case false is returned.
+    fn fade_temperature(
+        &mut self,
+        temp: Temperature,
+        config: &Configuration,
+        displays: &[String],
+        only: Option<&str>,
+        fade: &Fade,
+        wait: &mut dyn FnMut(Duration) -> bool,
+    ) -> Result<bool> {
+        let from = state::read::<Temperature>(displays).unwrap_or(temp);
+        for step in fade.steps(from, temp) {
+            self.apply_temperature(step, config, displays, only)?;
+            // Keep track of where we are in case the fade gets interrupted
+            if only.is_none() {
+                state::write(displays, step)?;
+            }
+            if !wait(FADE_INTERVAL) {
+                return Ok(false);
+            }
+        }
+
+        match only {
+            Some(_) => self.apply_temperature(temp, config, displays, only)?,
+            None => self.set_temperature(temp, config, displays)?,
+        };
+
+        Ok(true)
+    }
+
+    /// Applies the temperature to all enabled outputs, or only to the output named `only`,
+    /// taking the per output settings from the configuration into account. The brightness and
+    /// gamma correction are read from the state of `displays`.
+    fn apply_temperature(
+        &mut self,
+        temp: Temperature,
+        config: &Configuration,
+        displays: &[String],
+        only: Option<&str>,
+    ) -> Result<()> {
+        if only.is_some() && !self.capabilities().per_output {
+            anyhow::bail!("Backend does not support setting gamma per output");
+        }
+
+        let outputs: Vec<Output> = self
+            .outputs()?
+            .into_iter()
+            .filter(|output| output.id.is_some())
+            .filter(|output| only.is_none_or(|name| output.name == name))
+            .collect();
+
+        if let (Some(name), true) = (only, outputs.is_empty()) {
+            anyhow::bail!("No enabled output named {name}");
+        }
+
+        let brightness = state::read::<Brightness>(displays)
+            .unwrap_or_default()
+            .as_f64();
+        let correction = state::read::<GammaCorrection>(displays).unwrap_or(config.gamma);
+        let mut applied = Vec::with_capacity(outputs.len());
+        for output in outputs {
+            // Mirrored outputs share the same CRTC
+            if applied.contains(&output.id) {
+                continue;
+            }
+
+            let temp = config.output_temperature(&output.name, temp);
+            let (gamma_r, gamma_g, gamma_b) = config.color_algorithm.temp_to_gamma(temp.as_f64());
+            let size = self.ramp_size(&output)?;
+            let gamma = Gamma::new(
+                size,
+                gamma_r * brightness,
+                gamma_g * brightness,
+                gamma_b * brightness,
+                &correction,
+            );
+            self.set_ramp(&output, &gamma)?;
+            applied.push(output.id);
+        }
+
+        self.flush()
+    }
 }
 
-// TTY
+/// Entry in the backend registry, used to select a backend by name from the cli or config.
+#[derive(Debug, Clone, Copy)]
+pub struct Backend {
+    name: &'static str,
+    init: fn(&BackendOptions) -> Box<dyn GammaBackend>,
+}
 
-static TTY_COLOR_TABLE: &[&str] = &[
-    "000000", "aa0000", "00aa00", "aa5500", "0000aa", "aa00aa", "00aaaa", "aaaaaa", "555555",
-    "ff5555", "55ff55", "ffff55", "5555ff", "ff55ff", "55ffff", "ffffff",
+static BACKENDS: &[Backend] = &[
+    Backend {
+        name: "x11",
+        init: |options| Box::new(x11::X11Backend::new(options)),
+    },
+    Backend {
+        name: "tty",
+        init: |_| Box::<tty::TtyBackend>::default(),
+    },
+    Backend {
+        name: "wayland",
+        init: |_| Box::<wayland::WaylandBackend>::default(),
+    },
+    Backend {
+        name: "console",
+        init: |options| Box::new(console::ConsoleBackend::new(options)),
+    },
+    Backend {
+        name: "drm",
+        init: |options| Box::new(drm::DrmBackend::new(options)),
+    },
 ];
 
-pub fn set_gamma_for_tty(gamma_r: f64, gamma_g: f64, gamma_b: f64) -> Result<()> {
-    #[allow(clippy::needless_range_loop)]
-    for i in 0..TTY_COLOR_TABLE.len() {
-        let color = TTY_COLOR_TABLE[i];
-        let flt_to_hex = |flt: f64| -> String {
-            let flt = if flt > 255.0 { 255.0 } else { flt };
-            format!("{:02x}", flt as u8)
-        };
+impl Backend {
+    pub fn names() -> Vec<&'static str> {
+        BACKENDS.iter().map(|backend| backend.name).collect()
+    }
 
-        let hex_r = flt_to_hex(gamma_r * u8::from_str_radix(&color[0..2], 16)? as f64);
-        let hex_g = flt_to_hex(gamma_g * u8::from_str_radix(&color[2..4], 16)? as f64);
-        let hex_b = flt_to_hex(gamma_b * u8::from_str_radix(&color[4..6], 16)? as f64);
+    /// Capabilities of the backend, known without connecting to it
+    pub fn capabilities(&self) -> Capabilities {
+        (self.init)(&BackendOptions::default()).capabilities()
+    }
 
-        let string = format!("{:X}{}{}{}", i, hex_r, hex_g, hex_b);
+    /// Creates a new instance of the backend and connects it to the display server.
+    pub fn connect(&self, options: &BackendOptions) -> Result<Box<dyn GammaBackend>> {
+        let mut backend = (self.init)(options);
+        backend.connect()?;
+        Ok(backend)
+    }
+}
 
-        print!("\x1B]P{}", string);
+impl Default for Backend {
+    fn default() -> Self {
+        BACKENDS[0]
     }
+}
 
-    Ok(())
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl<'a> Deserialize<'a> for Backend {
+    fn deserialize<D>(deserializer: D) -> Result<Backend, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Backend::try_from(s.as_str()).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Backend {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.name)
+    }
+}
+
+impl TryFrom<&str> for Backend {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match BACKENDS
+            .iter()
+            .find(|backend| backend.name.eq_ignore_ascii_case(value))
+        {
+            Some(backend) => Ok(*backend),
+            None => anyhow::bail!("Invalid backend"),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -147,3 +476,46 @@ pub struct Gamma {
     green: GammaValue,
     blue: GammaValue,
 }
+
+impl Gamma {
+    /// Builds a ramp of `size` entries scaled by the gamma value of each channel, the ramp is
+    /// linear unless `correction` bends it.
+    pub fn new(
+        size: usize,
+        gamma_r: f64,
+        gamma_g: f64,
+        gamma_b: f64,
+        correction: &GammaCorrection,
+    ) -> Self {
+        let start = 0_u16;
+        let mut gamma = Gamma {
+            red: vec![start; size],
+            green: vec![start; size],
+            blue: vec![start; size],
+        };
+
+        let [exp_r, exp_g, exp_b] = correction.channels();
+        let channel =
+            |g: f64, scale: f64, exponent: f64| (65535.0 * (g * scale).powf(1.0 / exponent)) as u16;
+        for i in 0..size {
+            let g = (i as f64) / (size as f64);
+            gamma.red[i] = channel(g, gamma_r, exp_r);
+            gamma.green[i] = channel(g, gamma_g, exp_g);
+            gamma.blue[i] = channel(g, gamma_b, exp_b);
+        }
+
+        gamma
+    }
+
+    /// Scale of each channel compared to a linear ramp, the inverse of `new` without correction
+    pub fn multipliers(&self) -> (f64, f64, f64) {
+        let scale = |ramp: &[u16]| match ramp.len() {
+            0 => 0.0,
+            size => {
+                let linear = 65535.0 * (size - 1) as f64 / size as f64;
+                (ramp[size - 1] as f64 / linear).min(1.0)
+            }
+        };
+        (scale(&self.red), scale(&self.green), scale(&self.blue))
+    }
+}
@@ -0,0 +1,207 @@
+use super::{BackendOptions, Capabilities, Gamma, GammaBackend, Output};
+use crate::state;
+use anyhow::Result;
+use bluegone::StateFileName;
+use std::{
+    fs::{File, OpenOptions},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    path::{Path, PathBuf},
+};
+
+/// Reads the 16 color palette of the virtual consoles, see `man 2 ioctl_console`
+const GIO_CMAP: libc::c_ulong = 0x4B70;
+/// Writes the palette, it applies to every virtual console at once
+const PIO_CMAP: libc::c_ulong = 0x4B71;
+
+/// Consoles tried when none is selected, the current one and the controlling terminal
+static CONSOLES: &[&str] = &["/dev/tty0", "/dev/tty"];
+
+/// Every color component of the palette is looked up in a ramp of this size.
+const RAMP_SIZE: usize = 256;
+
+/// Red, green and blue of the 16 console colors.
+#[derive(Debug, Clone, Copy)]
+struct Palette([u8; 48]);
+
+impl std::fmt::Display for Palette {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for Palette {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        if value.len() != 96 {
+            anyhow::bail!("Palette must have 48 colors components, got `{value}`");
+        }
+
+        let mut palette = [0; 48];
+        for (i, byte) in palette.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&value[i * 2..i * 2 + 2], 16)?;
+        }
+        Ok(Palette(palette))
+    }
+}
+
+// The palette we started from is kept on disk, reading it back after tinting would stack
+// every change on top of the previous one.
+impl StateFileName for Palette {
+    fn name() -> String {
+        "palette".into()
+    }
+}
+
+#[derive(Default)]
+pub struct ConsoleBackend {
+    /// Console selected in the options, `None` tries each of `CONSOLES`
+    path: Option<PathBuf>,
+    /// Displays whose state keeps the original palette
+    displays: Vec<String>,
+    console: Option<Console>,
+}
+
+struct Console {
+    name: String,
+    file: File,
+    /// Palette as it was before we touched it
+    original: Palette,
+}
+
+impl Console {
+    fn open(path: &Path, displays: &[String]) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            // Don't become the controlling terminal of the daemon
+            .custom_flags(libc::O_NOCTTY)
+            .open(path)
+            .map_err(|err| anyhow::anyhow!("Unable to open {}: {err}", path.display()))?;
+
+        let mut console = Console {
+            name: path.display().to_string(),
+            file,
+            original: Palette([0; 48]),
+        };
+
+        let current = console
+            .palette()
+            .map_err(|err| anyhow::anyhow!("{} is not a virtual console: {err}", console.name))?;
+        // Only written once the palette is changed, reading it doesn't touch the state
+        console.original = state::read::<Palette>(displays).unwrap_or(current);
+
+        Ok(console)
+    }
+
+    fn palette(&self) -> std::io::Result<Palette> {
+        let mut palette = Palette([0; 48]);
+        // SAFETY: GIO_CMAP writes exactly 48 bytes to the pointer
+        match unsafe { libc::ioctl(self.file.as_raw_fd(), GIO_CMAP, palette.0.as_mut_ptr()) } {
+            -1 => Err(std::io::Error::last_os_error()),
+            _ => Ok(palette),
+        }
+    }
+
+    fn set_palette(&self, palette: &Palette) -> Result<()> {
+        // SAFETY: PIO_CMAP reads exactly 48 bytes from the pointer
+        match unsafe { libc::ioctl(self.file.as_raw_fd(), PIO_CMAP, palette.0.as_ptr()) } {
+            -1 => anyhow::bail!(
+                "Unable to set the palette of {}: {}",
+                self.name,
+                std::io::Error::last_os_error()
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ConsoleBackend {
+    pub fn new(options: &BackendOptions) -> Self {
+        Self {
+            path: options.console.clone(),
+            displays: options.displays.clone(),
+            console: None,
+        }
+    }
+
+    fn console(&self) -> Result<&Console> {
+        match &self.console {
+            Some(console) => Ok(console),
+            None => anyhow::bail!("Not connected to a console"),
+        }
+    }
+}
+
+impl GammaBackend for ConsoleBackend {
+    fn connect(&mut self) -> Result<()> {
+        if let Some(path) = &self.path {
+            self.console = Some(Console::open(path, &self.displays)?);
+            return Ok(());
+        }
+
+        let mut errors = vec![];
+        for path in CONSOLES {
+            match Console::open(Path::new(path), &self.displays) {
+                Ok(console) => {
+                    self.console = Some(console);
+                    return Ok(());
+                }
+                Err(err) => errors.push(err.to_string()),
+            }
+        }
+
+        anyhow::bail!("No usable console found: {}", errors.join(", "))
+    }
+
+    fn outputs(&mut self) -> Result<Vec<Output>> {
+        Ok(vec![Output {
+            id: Some(0),
+            name: self.console()?.name.clone(),
+            connected: true,
+            crtc: None,
+        }])
+    }
+
+    fn ramp_size(&mut self, _output: &Output) -> Result<usize> {
+        Ok(RAMP_SIZE)
+    }
+
+    fn set_ramp(&mut self, _output: &Output, gamma: &Gamma) -> Result<()> {
+        let console = self.console()?;
+        if state::read::<Palette>(&self.displays).is_none() {
+            state::write(&self.displays, console.original)?;
+        }
+
+        let lookup = |ramp: &[u16], value: u8| {
+            let value = ramp[value as usize * ramp.len() / RAMP_SIZE] as f64 / 65535.0 * 255.0;
+            value.round().min(255.0) as u8
+        };
+
+        let mut palette = console.original;
+        for color in palette.0.chunks_exact_mut(3) {
+            color[0] = lookup(&gamma.red, color[0]);
+            color[1] = lookup(&gamma.green, color[1]);
+            color[2] = lookup(&gamma.blue, color[2]);
+        }
+
+        console.set_palette(&palette)
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        let console = self.console()?;
+        console.set_palette(&console.original)?;
+        state::clear::<Palette>(&self.displays)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            per_output: false,
+            persistent: true,
+        }
+    }
+}
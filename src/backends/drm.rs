@@ -0,0 +1,337 @@
+use super::{BackendOptions, Capabilities, Gamma, GammaBackend, Output};
+use anyhow::Result;
+use drm::control::{connector, crtc, property, Device as ControlDevice};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    os::fd::{AsFd, BorrowedFd},
+    path::{Path, PathBuf},
+};
+
+/// Directory the DRM device nodes are looked up in when no card is selected
+const DRI_PATH: &str = "/dev/dri";
+
+struct Card(File);
+
+impl AsFd for Card {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl drm::Device for Card {}
+impl ControlDevice for Card {}
+
+/// How the gamma of a CRTC is set
+#[derive(Debug, Clone, Copy)]
+enum GammaMethod {
+    /// The `GAMMA_LUT` property of drivers with color management, with the size it expects
+    Lut {
+        property: property::Handle,
+        size: usize,
+    },
+    /// The legacy gamma ioctl, `drmModeCrtcSetGamma` in libdrm
+    Legacy { size: usize },
+}
+
+#[derive(Default)]
+pub struct DrmBackend {
+    /// Card selected in the options, `None` opens the first one with CRTCs
+    path: Option<PathBuf>,
+    card: Option<(PathBuf, Card)>,
+    methods: HashMap<crtc::Handle, GammaMethod>,
+    /// Gamma ramps of every CRTC as they were when we connected
+    original: HashMap<crtc::Handle, Gamma>,
+}
+
+impl DrmBackend {
+    pub fn new(options: &BackendOptions) -> Self {
+        Self {
+            path: options.drm_card.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn card(&self) -> Result<&Card> {
+        match &self.card {
+            Some((_, card)) => Ok(card),
+            None => anyhow::bail!("Not connected to a DRM device"),
+        }
+    }
+
+    fn crtc(output: &Output) -> Result<crtc::Handle> {
+        match drm::control::from_u32(output.id()?) {
+            Some(crtc) => Ok(crtc),
+            None => anyhow::bail!("Output {} has an invalid CRTC", output.name),
+        }
+    }
+
+    fn method(&self, crtc: crtc::Handle) -> Result<GammaMethod> {
+        match self.methods.get(&crtc) {
+            Some(method) => Ok(*method),
+            None => anyhow::bail!("Unknown CRTC {crtc:?}"),
+        }
+    }
+
+    /// Prefers `GAMMA_LUT` when the driver exposes it
+    fn find_method(card: &Card, crtc: crtc::Handle) -> Result<GammaMethod> {
+        let properties = card.get_properties(crtc)?;
+        let mut lut = None;
+        let mut lut_size = None;
+        for (&handle, &value) in properties.iter() {
+            match card.get_property(handle)?.name().to_bytes() {
+                b"GAMMA_LUT" => lut = Some(handle),
+                b"GAMMA_LUT_SIZE" => lut_size = Some(value as usize),
+                _ => {}
+            }
+        }
+
+        match (lut, lut_size) {
+            (Some(property), Some(size)) if size > 0 => Ok(GammaMethod::Lut { property, size }),
+            _ => Ok(GammaMethod::Legacy {
+                size: card.get_crtc(crtc)?.gamma_length() as usize,
+            }),
+        }
+    }
+
+    fn read_ramp(&self, crtc: crtc::Handle) -> Result<Gamma> {
+        let card = self.card()?;
+        match self.method(crtc)? {
+            GammaMethod::Lut { property, size } => {
+                let blob = card
+                    .get_properties(crtc)?
+                    .iter()
+                    .find(|(&handle, _)| handle == property)
+                    .map_or(0, |(_, &value)| value);
+
+                // No blob means the hardware passes colors through unchanged
+                if blob == 0 {
+                    return Ok(Gamma::new(size, 1.0, 1.0, 1.0, &Default::default()));
+                }
+
+                let data = card.get_property_blob(blob)?;
+                Ok(lut_to_gamma(&data))
+            }
+            GammaMethod::Legacy { size } => {
+                let mut gamma = Gamma {
+                    red: vec![0; size],
+                    green: vec![0; size],
+                    blue: vec![0; size],
+                };
+                card.get_gamma(crtc, &mut gamma.red, &mut gamma.green, &mut gamma.blue)?;
+                Ok(gamma)
+            }
+        }
+    }
+
+    fn write_ramp(&mut self, crtc: crtc::Handle, gamma: &Gamma) -> Result<()> {
+        let card = self.card()?;
+        match self.method(crtc)? {
+            GammaMethod::Lut { property, size } => {
+                let mut data = gamma_to_lut(gamma, size);
+                let blob = drm_ffi::mode::create_property_blob(card.as_fd(), &mut data)?.blob_id;
+                let result = card.set_property(crtc, property, blob as u64);
+                // The CRTC keeps its own reference to the blob
+                card.destroy_property_blob(blob as u64)?;
+
+                if let Err(err) = result {
+                    log::warn!("Unable to set GAMMA_LUT of {crtc:?}, using legacy gamma: {err}");
+                    let size = card.get_crtc(crtc)?.gamma_length() as usize;
+                    self.methods.insert(crtc, GammaMethod::Legacy { size });
+                    return self.write_ramp(crtc, gamma);
+                }
+            }
+            GammaMethod::Legacy { size } => {
+                let red = resample(&gamma.red, size);
+                let green = resample(&gamma.green, size);
+                let blue = resample(&gamma.blue, size);
+                card.set_gamma(crtc, &red, &green, &blue).map_err(|err| {
+                    anyhow::anyhow!("Unable to set gamma, is another program DRM master? {err}")
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl GammaBackend for DrmBackend {
+    fn connect(&mut self) -> Result<()> {
+        let paths = match &self.path {
+            Some(path) => vec![path.clone()],
+            None => {
+                let mut paths: Vec<PathBuf> = std::fs::read_dir(DRI_PATH)
+                    .map_err(|err| anyhow::anyhow!("Unable to list {DRI_PATH}: {err}"))?
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| {
+                        path.file_name()
+                            .is_some_and(|name| name.to_string_lossy().starts_with("card"))
+                    })
+                    .collect();
+                paths.sort();
+                paths
+            }
+        };
+
+        let mut errors = vec![];
+        for path in paths {
+            match open_card(&path) {
+                Ok(card) => {
+                    self.card = Some((path, card));
+                    break;
+                }
+                Err(err) => errors.push(format!("{}: {err}", path.display())),
+            }
+        }
+
+        let card = match &self.card {
+            Some((_, card)) => card,
+            None => anyhow::bail!("No usable DRM device found: {}", errors.join(", ")),
+        };
+
+        for &crtc in card.resource_handles()?.crtcs() {
+            let method = Self::find_method(card, crtc)?;
+            self.methods.insert(crtc, method);
+        }
+        for &crtc in self.methods.keys() {
+            self.original.insert(crtc, self.read_ramp(crtc)?);
+        }
+
+        Ok(())
+    }
+
+    fn outputs(&mut self) -> Result<Vec<Output>> {
+        let card = self.card()?;
+        let resources = card.resource_handles()?;
+
+        let mut outputs = Vec::with_capacity(resources.connectors().len());
+        for &handle in resources.connectors() {
+            let info = card.get_connector(handle, false)?;
+            // Disabled connectors aren't driven by an encoder and CRTC
+            let crtc = match info.current_encoder() {
+                Some(encoder) => card.get_encoder(encoder)?.crtc(),
+                None => None,
+            };
+
+            outputs.push(Output {
+                id: crtc.map(u32::from),
+                name: info.to_string(),
+                connected: info.state() == connector::State::Connected,
+                crtc: crtc.map(u32::from),
+            });
+        }
+
+        Ok(outputs)
+    }
+
+    fn ramp_size(&mut self, output: &Output) -> Result<usize> {
+        match self.method(Self::crtc(output)?)? {
+            GammaMethod::Lut { size, .. } | GammaMethod::Legacy { size } => Ok(size),
+        }
+    }
+
+    fn set_ramp(&mut self, output: &Output, gamma: &Gamma) -> Result<()> {
+        self.write_ramp(Self::crtc(output)?, gamma)
+    }
+
+    fn current_ramp(&mut self, output: &Output) -> Result<Option<Gamma>> {
+        Ok(Some(self.read_ramp(Self::crtc(output)?)?))
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        for (crtc, gamma) in std::mem::take(&mut self.original) {
+            self.write_ramp(crtc, &gamma)?;
+        }
+
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            per_output: true,
+            persistent: true,
+        }
+    }
+}
+
+fn open_card(path: &Path) -> Result<Card> {
+    let card = Card(OpenOptions::new().read(true).write(true).open(path)?);
+    if card.resource_handles()?.crtcs().is_empty() {
+        anyhow::bail!("Device has no CRTCs");
+    }
+
+    Ok(card)
+}
+
+/// Picks `size` entries spread evenly over the ramp
+fn resample(ramp: &[u16], size: usize) -> Vec<u16> {
+    // There is nothing to sample from an empty ramp, pass colors through unchanged instead
+    if ramp.is_empty() {
+        return (0..size).map(|i| (65535 * i / size) as u16).collect();
+    }
+
+    (0..size).map(|i| ramp[i * ramp.len() / size]).collect()
+}
+
+/// Encodes the ramp as an array of `struct drm_color_lut`, four u16 per entry
+fn gamma_to_lut(gamma: &Gamma, size: usize) -> Vec<u8> {
+    let red = resample(&gamma.red, size);
+    let green = resample(&gamma.green, size);
+    let blue = resample(&gamma.blue, size);
+
+    let mut data = Vec::with_capacity(size * 8);
+    for i in 0..size {
+        for value in [red[i], green[i], blue[i], 0] {
+            data.extend_from_slice(&value.to_ne_bytes());
+        }
+    }
+    data
+}
+
+fn lut_to_gamma(data: &[u8]) -> Gamma {
+    let mut gamma = Gamma {
+        red: vec![],
+        green: vec![],
+        blue: vec![],
+    };
+
+    for entry in data.chunks_exact(8) {
+        let value = |i: usize| u16::from_ne_bytes([entry[i], entry[i + 1]]);
+        gamma.red.push(value(0));
+        gamma.green.push(value(2));
+        gamma.blue.push(value(4));
+    }
+    gamma
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lut_round_trips_gamma() {
+        let gamma = Gamma::new(256, 1.0, 0.8, 0.5, &Default::default());
+        let data = gamma_to_lut(&gamma, 256);
+        assert_eq!(data.len(), 256 * 8);
+
+        let decoded = lut_to_gamma(&data);
+        assert_eq!(decoded.red, gamma.red);
+        assert_eq!(decoded.green, gamma.green);
+        assert_eq!(decoded.blue, gamma.blue);
+    }
+
+    #[test]
+    fn resample_spreads_entries_over_ramp() {
+        let ramp = [0, 10, 20, 30, 40, 50, 60, 70];
+        assert_eq!(resample(&ramp, 4), [0, 20, 40, 60]);
+        assert_eq!(resample(&ramp[..2], 4), [0, 0, 10, 10]);
+        assert_eq!(resample(&ramp, 8), ramp);
+        assert!(resample(&ramp, 0).is_empty());
+    }
+
+    #[test]
+    fn resample_empty_ramp_is_linear() {
+        assert_eq!(resample(&[], 4), [0, 16383, 32767, 49151]);
+    }
+}
@@ -0,0 +1,72 @@
+use super::{Capabilities, Gamma, GammaBackend, Output};
+use anyhow::Result;
+use std::io::Write;
+
+static TTY_COLOR_TABLE: &[&str] = &[
+    "000000", "aa0000", "00aa00", "aa5500", "0000aa", "aa00aa", "00aaaa", "aaaaaa", "555555",
+    "ff5555", "55ff55", "ffff55", "5555ff", "ff55ff", "55ffff", "ffffff",
+];
+
+/// Every color component of the palette is looked up in a ramp of this size.
+const RAMP_SIZE: usize = 256;
+
+#[derive(Default)]
+pub struct TtyBackend;
+
+impl GammaBackend for TtyBackend {
+    fn connect(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn outputs(&mut self) -> Result<Vec<Output>> {
+        Ok(vec![Output {
+            id: Some(0),
+            name: "tty".into(),
+            connected: true,
+            crtc: None,
+        }])
+    }
+
+    fn ramp_size(&mut self, _output: &Output) -> Result<usize> {
+        Ok(RAMP_SIZE)
+    }
+
+    fn set_ramp(&mut self, _output: &Output, gamma: &Gamma) -> Result<()> {
+        let lookup = |ramp: &[u16], hex: &str| -> Result<String> {
+            let color = u8::from_str_radix(hex, 16)? as usize;
+            let value = ramp[color * ramp.len() / RAMP_SIZE] as f64 / 65535.0 * 255.0;
+            let value = if value > 255.0 { 255.0 } else { value.round() };
+            Ok(format!("{:02x}", value as u8))
+        };
+
+        for (i, color) in TTY_COLOR_TABLE.iter().enumerate() {
+            let hex_r = lookup(&gamma.red, &color[0..2])?;
+            let hex_g = lookup(&gamma.green, &color[2..4])?;
+            let hex_b = lookup(&gamma.blue, &color[4..6])?;
+
+            let string = format!("{:X}{}{}{}", i, hex_r, hex_g, hex_b);
+
+            print!("\x1B]P{}", string);
+        }
+
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        // Resets the palette to the console defaults
+        print!("\x1B]R");
+        self.flush()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            per_output: false,
+            persistent: true,
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        std::io::stdout().flush()?;
+        Ok(())
+    }
+}
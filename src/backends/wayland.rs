@@ -0,0 +1,287 @@
+use super::{Capabilities, Gamma, GammaBackend, Output};
+use anyhow::Result;
+use std::{
+    io::{Seek, SeekFrom, Write},
+    os::fd::AsFd,
+};
+use wayland_client::{
+    globals::{registry_queue_init, GlobalListContents},
+    protocol::{wl_output, wl_registry},
+    Connection, Dispatch, EventQueue, QueueHandle,
+};
+use wayland_protocols_wlr::gamma_control::v1::client::{
+    zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1,
+    zwlr_gamma_control_v1::{self, ZwlrGammaControlV1},
+};
+
+#[derive(Default)]
+pub struct WaylandBackend {
+    client: Option<WaylandClient>,
+}
+
+// The compositor restores the original gamma once our gamma controls are destroyed,
+// so the connection has to be kept around for as long as the gamma should be applied.
+struct WaylandClient {
+    // Dropping the connection disconnects us from the compositor
+    _conn: Connection,
+    queue: EventQueue<State>,
+    state: State,
+    manager: ZwlrGammaControlManagerV1,
+}
+
+#[derive(Default)]
+struct State {
+    outputs: Vec<WaylandOutput>,
+}
+
+struct WaylandOutput {
+    name: Option<String>,
+    output: wl_output::WlOutput,
+    /// Only taken once gamma is set, a compositor gives control to a single client at a time
+    control: Option<ZwlrGammaControlV1>,
+    size: Option<usize>,
+    failed: bool,
+}
+
+impl WaylandClient {
+    fn connect() -> Result<Self> {
+        let conn = Connection::connect_to_env()?;
+        let (globals, mut queue) = registry_queue_init::<State>(&conn)?;
+        let qh = queue.handle();
+
+        let manager: ZwlrGammaControlManagerV1 = match globals.bind(&qh, 1..=1, ()) {
+            Ok(manager) => manager,
+            Err(_) => anyhow::bail!("Compositor does not support wlr-gamma-control-unstable-v1"),
+        };
+
+        let mut state = State::default();
+        for global in globals.contents().clone_list() {
+            if global.interface != "wl_output" {
+                continue;
+            }
+
+            let output: wl_output::WlOutput = globals.registry().bind(
+                global.name,
+                global.version.min(4),
+                &qh,
+                state.outputs.len(),
+            );
+
+            state.outputs.push(WaylandOutput {
+                name: None,
+                output,
+                control: None,
+                size: None,
+                failed: false,
+            });
+        }
+
+        // Wait for the compositor to send the name of every output
+        queue.roundtrip(&mut state)?;
+
+        Ok(Self {
+            _conn: conn,
+            queue,
+            state,
+            manager,
+        })
+    }
+
+    /// Takes control of the gamma of every output, unless that already happened
+    fn take_control(&mut self) -> Result<()> {
+        if self
+            .state
+            .outputs
+            .iter()
+            .all(|output| output.control.is_some())
+        {
+            return Ok(());
+        }
+
+        let qh = self.queue.handle();
+        for (i, output) in self.state.outputs.iter_mut().enumerate() {
+            if output.control.is_none() {
+                output.control = Some(self.manager.get_gamma_control(&output.output, &qh, i));
+            }
+        }
+
+        // Wait for the compositor to send the gamma size of every output
+        self.queue.roundtrip(&mut self.state)?;
+        Ok(())
+    }
+
+    fn output(&self, output: &Output) -> Result<&WaylandOutput> {
+        match self.state.outputs.get(output.id()? as usize) {
+            Some(output) => Ok(output),
+            None => anyhow::bail!("No such output: {}", output.name),
+        }
+    }
+}
+
+impl WaylandBackend {
+    fn client(&mut self) -> Result<&mut WaylandClient> {
+        match &mut self.client {
+            Some(client) => Ok(client),
+            None => anyhow::bail!("Not connected to the Wayland compositor"),
+        }
+    }
+}
+
+impl GammaBackend for WaylandBackend {
+    fn connect(&mut self) -> Result<()> {
+        self.client = Some(WaylandClient::connect()?);
+        Ok(())
+    }
+
+    fn outputs(&mut self) -> Result<Vec<Output>> {
+        let client = self.client()?;
+        Ok(client
+            .state
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(i, output)| Output {
+                id: Some(i as u32),
+                name: output.name.clone().unwrap_or_else(|| format!("output-{i}")),
+                connected: true,
+                crtc: None,
+            })
+            .collect())
+    }
+
+    fn ramp_size(&mut self, output: &Output) -> Result<usize> {
+        let client = self.client()?;
+        client.take_control()?;
+        // Outputs we failed to take control of are skipped by `set_ramp`
+        Ok(client.output(output)?.size.unwrap_or(0))
+    }
+
+    fn set_ramp(&mut self, output: &Output, gamma: &Gamma) -> Result<()> {
+        let client = self.client()?;
+        client.take_control()?;
+        let wayland_output = client.output(output)?;
+        let control = match &wayland_output.control {
+            Some(control) if !wayland_output.failed && wayland_output.size.is_some() => control,
+            _ => {
+                log::warn!(
+                    "Unable to control gamma for output {}, is another client running?",
+                    output.name
+                );
+                return Ok(());
+            }
+        };
+
+        let mut file = tempfile::tempfile()?;
+        for value in gamma.red.iter().chain(&gamma.green).chain(&gamma.blue) {
+            file.write_all(&value.to_ne_bytes())?;
+        }
+        file.flush()?;
+        file.seek(SeekFrom::Start(0))?;
+
+        control.set_gamma(file.as_fd());
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        // Destroying the gamma controls makes the compositor restore the original ramps
+        if let Some(mut client) = self.client.take() {
+            for control in client
+                .state
+                .outputs
+                .iter()
+                .filter_map(|o| o.control.as_ref())
+            {
+                control.destroy();
+            }
+            client.queue.roundtrip(&mut client.state)?;
+        }
+
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            per_output: true,
+            persistent: false,
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let client = self.client()?;
+        client.queue.roundtrip(&mut client.state)?;
+
+        let controlled = client
+            .state
+            .outputs
+            .iter()
+            .any(|output| output.control.is_some());
+        if controlled && client.state.outputs.iter().all(|output| output.failed) {
+            anyhow::bail!("Unable to control gamma for any output, is another client running?");
+        }
+
+        Ok(())
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, usize> for State {
+    fn event(
+        state: &mut Self,
+        _: &wl_output::WlOutput,
+        event: wl_output::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_output::Event::Name { name } = event {
+            if let Some(output) = state.outputs.get_mut(*index) {
+                output.name = Some(name);
+            }
+        }
+    }
+}
+
+impl Dispatch<ZwlrGammaControlManagerV1, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &ZwlrGammaControlManagerV1,
+        _: <ZwlrGammaControlManagerV1 as wayland_client::Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrGammaControlV1, usize> for State {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrGammaControlV1,
+        event: zwlr_gamma_control_v1::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let output = match state.outputs.get_mut(*index) {
+            Some(output) => output,
+            None => return,
+        };
+
+        match event {
+            zwlr_gamma_control_v1::Event::GammaSize { size } => output.size = Some(size as usize),
+            zwlr_gamma_control_v1::Event::Failed => output.failed = true,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use super::{BackendOptions, Capabilities, Gamma, GammaBackend, Output, OutputsChanged};
+use anyhow::Result;
+use std::{collections::HashMap, thread};
+use x11rb::connection::Connection;
+use x11rb::protocol::randr::{self, *};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+/// X resource ids leave their top three bits unused, output ids keep the index of the display there
+const DISPLAY_SHIFT: u32 = 29;
+const MAX_DISPLAYS: usize = 1 << (32 - DISPLAY_SHIFT);
+
+fn output_id(display: usize, crtc: Crtc) -> u32 {
+    (display as u32) << DISPLAY_SHIFT | crtc
+}
+
+/// Index of the display and CRTC an output id refers to
+fn split_output_id(id: u32) -> (usize, Crtc) {
+    (
+        (id >> DISPLAY_SHIFT) as usize,
+        id & ((1 << DISPLAY_SHIFT) - 1),
+    )
+}
+
+/// Connects to every selected display, or the one in `$DISPLAY`, and sets the gamma of the
+/// CRTCs of all their screens.
+#[derive(Default)]
+pub struct X11Backend {
+    /// Displays selected in the options, empty connects to `$DISPLAY`
+    names: Vec<String>,
+    displays: Vec<X11Display>,
+    /// Kept to watch the new X server after reconnecting
+    watcher: Option<OutputsChanged>,
+}
+
+/// The connection is kept for as long as the backend lives, the daemon would otherwise
+/// reconnect and query every CRTC on each step of a fade.
+struct X11Display {
+    /// `None` connects to `$DISPLAY`
+    name: Option<String>,
+    conn: Option<RustConnection>,
+    /// Outputs of all screens, dropped when RandR reports a change
+    outputs: Option<Vec<Output>>,
+    /// Gamma ramp size of every CRTC, dropped along with `outputs`
+    ramp_sizes: HashMap<Crtc, usize>,
+    /// Gamma ramps of every CRTC as they were when we connected
+    original: HashMap<Crtc, Gamma>,
+}
+
+impl X11Display {
+    fn new(name: Option<String>) -> Self {
+        X11Display {
+            name,
+            conn: None,
+            outputs: None,
+            ramp_sizes: HashMap::new(),
+            original: HashMap::new(),
+        }
+    }
+
+    fn conn(&self) -> Result<&RustConnection> {
+        match &self.conn {
+            Some(conn) => Ok(conn),
+            None => anyhow::bail!("Not connected to the X server"),
+        }
+    }
+
+    /// Resources of every screen, there's more than one in Zaphod setups
+    fn screen_resources(&self) -> Result<Vec<GetScreenResourcesCurrentReply>> {
+        let conn = self.conn()?;
+        let mut resources = vec![];
+        for screen in &conn.setup().roots {
+            resources.push(
+                conn.randr_get_screen_resources_current(screen.root)?
+                    .reply()?,
+            );
+        }
+        Ok(resources)
+    }
+
+    fn connect(&mut self) -> Result<()> {
+        self.conn = Some(connect_with_notifications(self.name.as_deref())?);
+
+        for res in self.screen_resources()? {
+            for crtc in res.crtcs {
+                let reply = self.conn()?.randr_get_crtc_gamma(crtc)?.reply()?;
+                let gamma = Gamma {
+                    red: reply.red,
+                    green: reply.green,
+                    blue: reply.blue,
+                };
+                self.original.insert(crtc, gamma);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles the events received since the last call, the cached outputs are dropped when
+    /// RandR reports a change. Returns false when the X server went away.
+    fn refresh(&mut self) -> Result<bool> {
+        if self.conn.is_none() {
+            return Ok(false);
+        }
+
+        let mut changed = false;
+        loop {
+            match self.conn()?.poll_for_event() {
+                Ok(Some(Event::RandrScreenChangeNotify(_) | Event::RandrNotify(_))) => {
+                    changed = true
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(err) => {
+                    log::warn!("Lost connection to the X server, reconnecting: {err}");
+                    return Ok(false);
+                }
+            }
+        }
+
+        if changed {
+            log::debug!("RandR configuration changed, querying outputs again");
+            self.invalidate();
+        }
+
+        Ok(true)
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        self.conn = None;
+        self.invalidate();
+        // Whatever was applied before is gone along with the old server
+        self.original.clear();
+        self.connect()
+    }
+
+    fn invalidate(&mut self) {
+        self.outputs = None;
+        self.ramp_sizes.clear();
+    }
+
+    /// Names are prefixed with the display when `qualify` is set, e.g. `:1/DP-1`, as outputs of
+    /// different displays can share a name
+    fn outputs(&mut self, index: usize, qualify: bool) -> Result<Vec<Output>> {
+        if let Some(outputs) = &self.outputs {
+            return Ok(outputs.clone());
+        }
+
+        let conn = self.conn()?;
+        let mut outputs = vec![];
+        for res in self.screen_resources()? {
+            for &output in &res.outputs {
+                let info = conn
+                    .randr_get_output_info(output, res.config_timestamp)?
+                    .reply()?;
+
+                outputs.push(Output {
+                    // Disabled outputs aren't assigned to a CRTC
+                    id: match info.crtc {
+                        x11rb::NONE => None,
+                        crtc => Some(output_id(index, crtc)),
+                    },
+                    name: match (&self.name, qualify) {
+                        (Some(display), true) => {
+                            format!("{display}/{}", String::from_utf8_lossy(&info.name))
+                        }
+                        _ => String::from_utf8_lossy(&info.name).into_owned(),
+                    },
+                    connected: info.connection == randr::Connection::CONNECTED,
+                    crtc: match info.crtc {
+                        x11rb::NONE => None,
+                        crtc => Some(crtc),
+                    },
+                });
+            }
+        }
+
+        self.outputs = Some(outputs.clone());
+        Ok(outputs)
+    }
+
+    fn ramp_size(&mut self, crtc: Crtc) -> Result<usize> {
+        if let Some(&size) = self.ramp_sizes.get(&crtc) {
+            return Ok(size);
+        }
+
+        let size = self.conn()?.randr_get_crtc_gamma_size(crtc)?.reply()?.size as usize;
+        self.ramp_sizes.insert(crtc, size);
+        Ok(size)
+    }
+
+    fn restore(&self) -> Result<()> {
+        let conn = self.conn()?;
+        for (&crtc, gamma) in &self.original {
+            conn.randr_set_crtc_gamma(crtc, &gamma.red, &gamma.green, &gamma.blue)?;
+        }
+        conn.flush()?;
+        Ok(())
+    }
+
+    /// Calls `changed` with the ids of CRTCs that were reconfigured, until the connection breaks
+    fn watch(&self, index: usize, changed: OutputsChanged) -> Result<()> {
+        // The main connection is busy with requests whenever gamma is applied,
+        // so events are waited for on a connection of their own
+        let conn = connect_with_notifications(self.name.as_deref())?;
+
+        thread::spawn(move || loop {
+            let event = match conn.wait_for_event() {
+                Ok(event) => event,
+                Err(err) => {
+                    // Watching starts over once the main connection reconnects
+                    log::warn!("Stopped watching for RandR changes: {err}");
+                    return;
+                }
+            };
+
+            // A hotplug comes with a burst of notifications, handle them at once
+            let mut crtcs: Vec<Crtc> = changed_crtc(&event).into_iter().collect();
+            while let Ok(Some(event)) = conn.poll_for_event() {
+                crtcs.extend(changed_crtc(&event));
+            }
+            crtcs.sort();
+            crtcs.dedup();
+
+            if !crtcs.is_empty() {
+                changed(crtcs.iter().map(|&crtc| output_id(index, crtc)).collect());
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Connects and asks for RandR change notifications of every screen
+fn connect_with_notifications(name: Option<&str>) -> Result<RustConnection> {
+    let (conn, _) = RustConnection::connect(name).map_err(|err| match name {
+        Some(name) => anyhow::anyhow!("Unable to connect to display {name}: {err}"),
+        None => err.into(),
+    })?;
+    // Change notifications are only sent to clients announcing RandR 1.2 or later
+    conn.randr_query_version(1, 3)?.reply()?;
+    for screen in &conn.setup().roots {
+        conn.randr_select_input(
+            screen.root,
+            NotifyMask::SCREEN_CHANGE | NotifyMask::CRTC_CHANGE | NotifyMask::OUTPUT_CHANGE,
+        )?;
+    }
+    Ok(conn)
+}
+
+/// CRTC a notification is about, if it's about one
+fn changed_crtc(event: &Event) -> Option<Crtc> {
+    match event {
+        Event::RandrNotify(notify) if notify.sub_code == Notify::CRTC_CHANGE => {
+            Some(notify.u.as_cc().crtc)
+        }
+        _ => None,
+    }
+}
+
+impl X11Backend {
+    pub fn new(options: &BackendOptions) -> Self {
+        Self {
+            names: options.displays.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Display and CRTC of an output
+    fn display(&mut self, output: &Output) -> Result<(&mut X11Display, Crtc)> {
+        let (index, crtc) = split_output_id(output.id()?);
+        match self.displays.get_mut(index) {
+            Some(display) => Ok((display, crtc)),
+            None => anyhow::bail!("Output {} belongs to an unknown display", output.name),
+        }
+    }
+}
+
+impl GammaBackend for X11Backend {
+    fn connect(&mut self) -> Result<()> {
+        let names = &self.names;
+        if names.len() > MAX_DISPLAYS {
+            anyhow::bail!("At most {MAX_DISPLAYS} X displays are supported");
+        }
+
+        self.displays = match names.is_empty() {
+            true => vec![X11Display::new(None)],
+            false => names
+                .iter()
+                .map(|name| X11Display::new(Some(name.clone())))
+                .collect(),
+        };
+
+        for display in &mut self.displays {
+            display.connect()?;
+        }
+
+        Ok(())
+    }
+
+    fn outputs(&mut self) -> Result<Vec<Output>> {
+        let qualify = self.displays.len() > 1;
+        let mut outputs = vec![];
+        for (index, display) in self.displays.iter_mut().enumerate() {
+            if !display.refresh()? {
+                display.reconnect()?;
+                if let Some(changed) = self.watcher.clone() {
+                    display.watch(index, changed)?;
+                }
+            }
+            outputs.extend(display.outputs(index, qualify)?);
+        }
+
+        Ok(outputs)
+    }
+
+    fn ramp_size(&mut self, output: &Output) -> Result<usize> {
+        let (display, crtc) = self.display(output)?;
+        display.ramp_size(crtc)
+    }
+
+    fn set_ramp(&mut self, output: &Output, gamma: &Gamma) -> Result<()> {
+        let (display, crtc) = self.display(output)?;
+        display
+            .conn()?
+            .randr_set_crtc_gamma(crtc, &gamma.red, &gamma.green, &gamma.blue)?;
+        Ok(())
+    }
+
+    fn current_ramp(&mut self, output: &Output) -> Result<Option<Gamma>> {
+        let (display, crtc) = self.display(output)?;
+        let reply = display.conn()?.randr_get_crtc_gamma(crtc)?.reply()?;
+        Ok(Some(Gamma {
+            red: reply.red,
+            green: reply.green,
+            blue: reply.blue,
+        }))
+    }
+
+    fn watch_outputs(&mut self, changed: OutputsChanged) -> Result<()> {
+        for (index, display) in self.displays.iter().enumerate() {
+            display.watch(index, changed.clone())?;
+        }
+        self.watcher = Some(changed);
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        for display in &self.displays {
+            display.restore()?;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            per_output: true,
+            persistent: true,
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for display in &self.displays {
+            display.conn()?.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -1,40 +1,270 @@
 use anyhow::Result;
 use bluegone::Pid;
 use clap::{builder::EnumValueParser, value_parser, Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use serde::Serialize;
+use std::{path::PathBuf, str::FromStr, thread, time::Duration};
 
 use crate::{
-    backends::{Backend, Temperature},
-    config::{Configuration, Mode},
-    daemon::{self, find_process_by_id, get_current_schedule, parse_schedule},
+    backends::{self, Backend, BackendOptions, Brightness, GammaCorrection, Output, Temperature},
+    config::{Configuration, Fade, Mode, ScheduleLightTrigger},
+    daemon::{
+        self, find_process_by_id, get_current_schedule, parse_schedule, Override, ScheduleBlock,
+        Toggled,
+    },
+    ipc::{self, Request, Response},
     state,
 };
 
+/// Output format of the subcommands that query information
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Format selected with the global `--format` option
+fn output_format(args: &ArgMatches) -> Format {
+    args.get_one::<Format>("format")
+        .copied()
+        .unwrap_or_default()
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string(value)?);
+    Ok(())
+}
+
 pub fn init_info_subcommand() -> Command {
     Command::new("info").about("List various configured options")
 }
 
+/// Everything `info` reports, `--format json` prints it as is
+#[derive(Serialize)]
+struct Info {
+    /// Pid of the daemon, `None` when it isn't running
+    pid: Option<u32>,
+    backend: Backend,
+    mode: Mode,
+    temperature: Option<Temperature>,
+    brightness: Brightness,
+    gamma: GammaCorrection,
+    /// Schedule block active right now
+    block: Option<ScheduleBlock>,
+    /// Block that follows the active one
+    next: Option<ScheduleBlock>,
+    /// Seconds until the next block starts
+    remaining: Option<i64>,
+    /// Temperature replacing the schedule for a while, see `set --for` and `pause`
+    #[serde(rename = "override")]
+    temporary: Option<Override>,
+    sunrise: Option<String>,
+    sunset: Option<String>,
+    /// `None` when the backend can't be reached, e.g. without `$DISPLAY`
+    outputs: Option<Vec<OutputGamma>>,
+}
+
+#[derive(Serialize)]
+struct OutputGamma {
+    name: String,
+    /// Red, green and blue multipliers between 0 and 1
+    gamma: Option<(f64, f64, f64)>,
+}
+
 pub fn handle_info_subcommand(
     args: &ArgMatches,
     backend: &Backend,
+    options: &BackendOptions,
     config: &Configuration,
     sys: &mut sysinfo::System,
 ) -> Result<()> {
-    let process = match state::read::<Pid>() {
+    let process = match state::read::<Pid>(&options.displays) {
         Some(pid) => find_process_by_id(pid, sys),
         None => None,
     };
 
+    let status = match process {
+        Some(_) => match ipc::send(&options.displays, &Request::GetStatus)? {
+            Some(Response::Status(status)) => Some(status),
+            _ => None,
+        },
+        None => None,
+    };
+
+    // The daemon might have been started with a different backend than the configured one
+    let backend = match &status {
+        Some(status) => Backend::try_from(status.backend.as_str())?,
+        None => *backend,
+    };
+
+    let temperature = state::read::<Temperature>(&options.displays);
+    let now = chrono::Local::now().time();
     let schedule = parse_schedule(config);
-    let schedule = get_current_schedule(schedule);
+    let block = get_current_schedule(&schedule, now).map(|(block, _)| block);
+    let next = block
+        .as_ref()
+        .and_then(|block| schedule.iter().find(|next| next.contains(block.end())));
+
+    let (sunrise, sunset) = match &config.location {
+        Some(location) => (
+            Some(ScheduleLightTrigger::Sunrise.get_time(location.clone())?),
+            Some(ScheduleLightTrigger::Sunset.get_time(location.clone())?),
+        ),
+        None => (None, None),
+    };
+
+    let info = Info {
+        pid: process.map(|process| process.pid().as_u32()),
+        backend,
+        mode: state::read::<Mode>(&options.displays).unwrap_or(config.mode.clone()),
+        temperature,
+        brightness: state::read(&options.displays).unwrap_or_default(),
+        gamma: state::read(&options.displays).unwrap_or(config.gamma),
+        remaining: block
+            .as_ref()
+            .map(|block| block.remaining(now).num_seconds()),
+        block: block.clone(),
+        next: next.cloned(),
+        temporary: state::read::<Override>(&options.displays)
+            .filter(|temporary| temporary.remaining().is_some()),
+        sunrise: sunrise.map(|time| time.format("%H:%M").to_string()),
+        sunset: sunset.map(|time| time.format("%H:%M").to_string()),
+        // Everything else is still worth reporting when the backend is out of reach
+        outputs: match output_gamma(&backend, options, config, temperature) {
+            Ok(outputs) => Some(outputs),
+            Err(err) => {
+                eprintln!("Unable to read the gamma of the outputs: {err}");
+                None
+            }
+        },
+    };
+
+    if output_format(args) == Format::Json {
+        return print_json(&info);
+    }
+
+    match info.pid {
+        Some(pid) => println!("Daemon:      active (pid: {pid})"),
+        None => println!("Daemon:      inactive"),
+    }
+    println!("Backend:     {}", info.backend);
+    println!("Mode:        {}", info.mode);
+    match info.temperature {
+        Some(temperature) => println!("Temperature: {}K", temperature.as_f64().round()),
+        None => println!("Temperature: unknown"),
+    }
+    println!("Brightness:  {}", info.brightness);
+    println!("Gamma:       {}", info.gamma);
+
+    if let Some(block) = &info.block {
+        println!(
+            "Block:       {}-{}, {}K ({})",
+            block.start().format("%H:%M"),
+            block.end().format("%H:%M"),
+            block.temperature(),
+            block_source(block)
+        );
+
+        let countdown = Duration::from_secs(info.remaining.unwrap_or_default() as u64 / 60 * 60);
+        println!(
+            "Next:        {}K at {} ({}), in {}",
+            block.next(),
+            block.end().format("%H:%M"),
+            info.next.as_ref().map(block_source).unwrap_or_default(),
+            humantime::format_duration(countdown)
+        );
+
+        if let Some(transition) = block.transition_length() {
+            let action = match block.in_transition(now) {
+                true => "started",
+                false => "starts",
+            };
+            println!(
+                "Transition:  {action} at {}",
+                (block.end() - transition).format("%H:%M")
+            );
+        }
+    }
+
+    if let Some(temporary) = &info.temporary {
+        let remaining = temporary.remaining().unwrap_or_default().to_std()?;
+        println!(
+            "Override:    {}K until {}, {} left",
+            temporary.temperature,
+            temporary.until.format("%H:%M"),
+            humantime::format_duration(Duration::from_secs(remaining.as_secs() / 60 * 60))
+        );
+    }
+
+    if let (Some(sunrise), Some(sunset)) = (&info.sunrise, &info.sunset) {
+        println!("Sunrise:     {sunrise}");
+        println!("Sunset:      {sunset}");
+    }
 
-    match process {
-        Some(process) => println!("Daemon active (pid: {})", process.pid()),
-        None => println!("Daemon inactive"),
+    let outputs = match &info.outputs {
+        Some(outputs) => outputs,
+        None => {
+            println!("Outputs:     unknown");
+            return Ok(());
+        }
+    };
+    if !outputs.is_empty() {
+        println!("Outputs:");
+    }
+    for output in outputs {
+        match output.gamma {
+            Some((r, g, b)) => println!("  {}: r {r:.3}, g {g:.3}, b {b:.3}", output.name),
+            None => println!("  {}: unknown", output.name),
+        }
     }
 
     Ok(())
 }
 
+/// Where the temperature of a block comes from, e.g. `preset night at sunset`
+fn block_source(block: &ScheduleBlock) -> String {
+    match block.preset() {
+        Some(preset) => format!("preset {preset} at {}", block.trigger()),
+        None => block.trigger().to_string(),
+    }
+}
+
+/// Gamma of every enabled output, read back from the backend when it supports it
+/// and otherwise derived from the last applied temperature.
+fn output_gamma(
+    backend: &Backend,
+    options: &BackendOptions,
+    config: &Configuration,
+    temperature: Option<Temperature>,
+) -> Result<Vec<OutputGamma>> {
+    let mut backend = backend.connect(options)?;
+    let mut gammas = vec![];
+    for output in backend.outputs()? {
+        if output.id.is_none() {
+            continue;
+        }
+
+        let gamma = match backend.current_ramp(&output)? {
+            Some(ramp) => Some(ramp.multipliers()),
+            None => temperature.map(|temperature| {
+                let temperature = config.output_temperature(&output.name, temperature);
+                let brightness = state::read::<Brightness>(&options.displays)
+                    .unwrap_or_default()
+                    .as_f64();
+                let (r, g, b) = config.color_algorithm.temp_to_gamma(temperature.as_f64());
+                (r * brightness, g * brightness, b * brightness)
+            }),
+        };
+
+        gammas.push(OutputGamma {
+            name: output.name,
+            gamma,
+        });
+    }
+
+    Ok(gammas)
+}
+
 pub fn init_set_subcommand() -> Command {
     Command::new("set")
         .about("Set temperature or preset")
@@ -42,8 +272,9 @@ pub fn init_set_subcommand() -> Command {
             Arg::new("temperature")
                 .short('t')
                 .long("temperature")
-                .help("Temperature to set in Kelvin, (0-6500)") // TODO: Custom parser with nice help message
-                .value_parser(value_parser!(f64)),
+                .allow_negative_numbers(true)
+                .help("Temperature to set in Kelvin, or a change like +500 or -250")
+                .value_parser(parse_temperature_change),
         )
         .arg(
             Arg::new("mode")
@@ -58,41 +289,472 @@ pub fn init_set_subcommand() -> Command {
                 .long("preset")
                 .help("Preset to apply"),
         )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .conflicts_with("mode")
+                .requires("temperature_source")
+                .help("Only apply to this output, e.g. DP-1"),
+        )
+        .arg(
+            Arg::new("fade")
+                .short('f')
+                .long("fade")
+                .conflicts_with("mode")
+                .requires("temperature_source")
+                .value_parser(humantime::parse_duration)
+                .help("Fade to the new temperature over the given duration, e.g. 2s"),
+        )
+        .arg(
+            Arg::new("for")
+                .long("for")
+                .conflicts_with_all(["mode", "output"])
+                .value_parser(humantime::parse_duration)
+                .help("Resume the schedule after the given duration, e.g. 90m"),
+        )
+        .arg(
+            Arg::new("brightness")
+                .long("brightness")
+                .conflicts_with_all(["mode", "for"])
+                .value_parser(parse_brightness)
+                .help("Brightness between 0.1 and 1.0, kept until changed again"),
+        )
+        .arg(
+            Arg::new("gamma")
+                .long("gamma")
+                .conflicts_with_all(["mode", "for"])
+                .value_parser(GammaCorrection::from_str)
+                .help("Gamma exponent for all channels or red:green:blue, e.g. 1.0:0.95:0.9"),
+        )
         .group(
             ArgGroup::new("set_target")
                 .args(["temperature", "mode", "preset"])
-                .required(true)
                 .multiple(false),
         )
+        // Brightness and gamma correction alone always apply to every output at once
+        .group(ArgGroup::new("temperature_source").args(["temperature", "preset"]))
+}
+
+fn parse_brightness(value: &str) -> Result<Brightness, String> {
+    let number = value
+        .parse::<f64>()
+        .map_err(|_| format!("`{value}` is not a number"))?;
+    Brightness::try_from(number)
+}
+
+/// Value of `set --temperature`, a leading sign makes it relative to the current temperature
+#[derive(Debug, Clone, Copy)]
+pub enum TemperatureChange {
+    Absolute(Temperature),
+    Relative(f64),
+}
+
+fn parse_temperature_change(value: &str) -> Result<TemperatureChange, String> {
+    let number = value
+        .parse::<f64>()
+        .map_err(|_| format!("`{value}` is not a temperature"))?;
+
+    match value.starts_with(['+', '-']) {
+        true => Ok(TemperatureChange::Relative(number)),
+        false => Ok(TemperatureChange::Absolute(Temperature::new(number))),
+    }
 }
 
 pub fn handle_set_subcommand(
     args: &ArgMatches,
     backend: &Backend,
+    options: &BackendOptions,
+    config: &Configuration,
+) -> Result<()> {
+    let output = args
+        .get_one::<String>("output")
+        .map(|output| output.as_str());
+    let fade = args.get_one::<Duration>("fade").copied();
+
+    let fade = fade.map(|fade| fade.as_millis() as u64);
+    let brightness = args.get_one::<Brightness>("brightness").copied();
+    let gamma = args.get_one::<GammaCorrection>("gamma").copied();
+
+    if let Some(duration) = args.get_one::<Duration>("for") {
+        let temperature = match args.get_one::<TemperatureChange>("temperature") {
+            Some(TemperatureChange::Absolute(temperature)) => *temperature,
+            Some(TemperatureChange::Relative(delta)) => {
+                let current = state::read::<Temperature>(&options.displays)
+                    .map_or(Temperature::NEUTRAL, |t| t.as_f64());
+                config.adjust.clamp(current + delta)
+            }
+            None => {
+                let Some(name) = args.get_one::<String>("preset") else {
+                    anyhow::bail!("--for requires a temperature or preset")
+                };
+                match config.presets.iter().find(|p| p.name == *name) {
+                    Some(preset) => preset.temperature,
+                    None => anyhow::bail!("No such preset: {name}"),
+                }
+            }
+        };
+
+        let until = chrono::Local::now() + chrono::Duration::from_std(*duration)?;
+        return dispatch(
+            override_request(temperature, until, fade),
+            backend,
+            options,
+            config,
+        );
+    }
+
+    let request = if let Some(value) = args.get_one::<TemperatureChange>("temperature") {
+        match *value {
+            TemperatureChange::Absolute(temperature) => Request::SetTemperature {
+                temperature,
+                output: output.map(String::from),
+                fade,
+                brightness,
+                gamma,
+            },
+            TemperatureChange::Relative(_) if output.is_some() => {
+                anyhow::bail!("Relative temperatures apply to every output")
+            }
+            TemperatureChange::Relative(_) if brightness.is_some() || gamma.is_some() => {
+                anyhow::bail!(
+                    "Relative temperatures can't be combined with --brightness or --gamma"
+                )
+            }
+            TemperatureChange::Relative(delta) => Request::AdjustTemperature { delta, fade },
+        }
+    } else if let Some(value) = args.get_one::<String>("preset") {
+        Request::ApplyPreset {
+            preset: value.clone(),
+            output: output.map(String::from),
+            fade,
+            brightness,
+            gamma,
+        }
+    } else if let Some(value) = args.get_one::<Mode>("mode") {
+        Request::SetMode {
+            mode: value.clone(),
+        }
+    } else if brightness.is_some() || gamma.is_some() {
+        Request::SetCorrection { brightness, gamma }
+    } else {
+        anyhow::bail!("No argument found")
+    };
+
+    dispatch(request, backend, options, config)
+}
+
+fn override_request(
+    temperature: Temperature,
+    until: chrono::DateTime<chrono::Local>,
+    fade: Option<u64>,
+) -> Request {
+    Request::SetOverride {
+        temperature,
+        until: until.timestamp(),
+        fade,
+    }
+}
+
+/// Lets the daemon handle the request if one is running, it owns the gamma of every output.
+/// Otherwise the request is applied directly.
+fn dispatch(
+    request: Request,
+    backend: &Backend,
+    options: &BackendOptions,
     config: &Configuration,
 ) -> Result<()> {
-    if let Some(value) = args.get_one::<f64>("temperature") {
-        let temperature = Temperature::new(value.to_owned());
-        backend.set_temperature(temperature)?;
-        state::write(Mode::Static)?;
+    if ipc::send(&options.displays, &request)?.is_some() {
         return Ok(());
     }
 
-    if let Some(value) = args.get_one::<String>("preset") {
-        let preset = config.presets.iter().find(|p| p.name == value.clone());
-        if let Some(preset) = preset {
-            backend.set_temperature(preset.temperature)?;
-            state::write(Mode::Static)?;
-            return Ok(());
+    let fade = |millis: Option<u64>| {
+        let duration = Duration::from_millis(millis.unwrap_or_default());
+        Fade::new(duration, config.fade.easing)
+    };
+
+    match request {
+        Request::SetTemperature {
+            temperature,
+            output,
+            fade: millis,
+            brightness,
+            gamma,
+        } => {
+            backends::write_correction(&options.displays, brightness, gamma)?;
+            apply_temperature(
+                backend,
+                options,
+                temperature,
+                config,
+                output.as_deref(),
+                &fade(millis),
+            )
+        }
+        Request::ApplyPreset {
+            preset,
+            output,
+            fade: millis,
+            brightness,
+            gamma,
+        } => match config.presets.iter().find(|p| p.name == preset) {
+            Some(preset) => {
+                backends::write_correction(
+                    &options.displays,
+                    brightness.or(preset.brightness),
+                    gamma.or(preset.gamma),
+                )?;
+                apply_temperature(
+                    backend,
+                    options,
+                    preset.temperature,
+                    config,
+                    output.as_deref(),
+                    &fade(millis),
+                )
+            }
+            None => anyhow::bail!("No such preset: {preset}"),
+        },
+        Request::SetCorrection { brightness, gamma } => {
+            backends::write_correction(&options.displays, brightness, gamma)?;
+            let temperature =
+                state::read(&options.displays).unwrap_or(Temperature::new(Temperature::NEUTRAL));
+            require_persistent(backend)?;
+            backend.connect(options)?.apply_temperature(
+                temperature,
+                config,
+                &options.displays,
+                None,
+            )
+        }
+        Request::AdjustTemperature {
+            delta,
+            fade: millis,
+        } => {
+            // Nothing runs the schedule without a daemon, so there's nothing to add an offset to.
+            // The mode stays as it is, a daemon started later still follows the schedule.
+            let current = state::read::<Temperature>(&options.displays)
+                .map_or(Temperature::NEUTRAL, |t| t.as_f64());
+            let temperature = config.adjust.clamp(current + delta);
+            fade_to(backend, options, temperature, config, None, &fade(millis))?;
+            state::clear::<Override>(&options.displays)?;
+            state::clear::<Toggled>(&options.displays)
+        }
+        Request::SetMode { mode } => {
+            state::clear::<Override>(&options.displays)?;
+            state::clear::<Toggled>(&options.displays)?;
+            state::write(&options.displays, mode)
+        }
+        Request::SetOverride { .. } => {
+            anyhow::bail!(
+                "Resuming the schedule requires a running daemon, see `bluegone daemon start`"
+            )
+        }
+        request => anyhow::bail!("{request:?} requires a running daemon"),
+    }
+}
+
+fn apply_temperature(
+    backend: &Backend,
+    options: &BackendOptions,
+    temperature: Temperature,
+    config: &Configuration,
+    output: Option<&str>,
+    fade: &Fade,
+) -> Result<()> {
+    fade_to(backend, options, temperature, config, output, fade)?;
+    state::write(&options.displays, Mode::Static)?;
+    state::clear::<Override>(&options.displays)?;
+    state::clear::<Toggled>(&options.displays)
+}
+
+/// Connects to the backend and fades to the temperature
+fn fade_to(
+    backend: &Backend,
+    options: &BackendOptions,
+    temperature: Temperature,
+    config: &Configuration,
+    output: Option<&str>,
+    fade: &Fade,
+) -> Result<()> {
+    require_persistent(backend)?;
+    let mut backend = backend.connect(options)?;
+    let displays = &options.displays;
+    backend.fade_temperature(
+        temperature,
+        config,
+        displays,
+        output,
+        fade,
+        &mut |duration| {
+            thread::sleep(duration);
+            true
+        },
+    )?;
+    Ok(())
+}
+
+/// The gamma of backends that aren't persistent is gone as soon as we exit, only the daemon
+/// can keep it applied
+fn require_persistent(backend: &Backend) -> Result<()> {
+    match backend.capabilities().persistent {
+        true => Ok(()),
+        false => anyhow::bail!(
+            "The {backend} backend only keeps gamma applied while the daemon runs, \
+             start it with `bluegone daemon start`"
+        ),
+    }
+}
+
+pub fn init_pause_subcommand() -> Command {
+    Command::new("pause")
+        .about("Switch to a neutral temperature for a while, then resume the schedule")
+        .arg(
+            Arg::new("until")
+                .long("until")
+                .value_parser(|value: &str| chrono::NaiveTime::parse_from_str(value, "%H:%M"))
+                .help("Time to resume at, e.g. 23:00"),
+        )
+        .arg(
+            Arg::new("for")
+                .long("for")
+                .value_parser(humantime::parse_duration)
+                .help("How long to pause for, e.g. 1h"),
+        )
+        .group(
+            ArgGroup::new("pause_length")
+                .args(["until", "for"])
+                .required(true)
+                .multiple(false),
+        )
+}
+
+pub fn handle_pause_subcommand(
+    args: &ArgMatches,
+    backend: &Backend,
+    options: &BackendOptions,
+    config: &Configuration,
+) -> Result<()> {
+    let now = chrono::Local::now();
+    let until = match (
+        args.get_one::<chrono::NaiveTime>("until"),
+        args.get_one::<Duration>("for"),
+    ) {
+        (Some(time), _) => {
+            // A time that already passed today means tomorrow
+            let mut date = now.date_naive();
+            if *time <= now.time() {
+                date = date.succ_opt().expect("date to be in range");
+            }
+            match date
+                .and_time(*time)
+                .and_local_timezone(chrono::Local)
+                .earliest()
+            {
+                Some(until) => until,
+                None => anyhow::bail!(
+                    "{} doesn't exist in the local timezone",
+                    time.format("%H:%M")
+                ),
+            }
         }
+        (None, Some(duration)) => now + chrono::Duration::from_std(*duration)?,
+        (None, None) => anyhow::bail!("No argument found"),
+    };
+
+    let temperature = Temperature::new(Temperature::NEUTRAL);
+    dispatch(
+        override_request(temperature, until, None),
+        backend,
+        options,
+        config,
+    )
+}
+
+pub fn init_toggle_subcommand() -> Command {
+    Command::new("toggle")
+        .about("Switch between a neutral temperature and the current schedule or preset")
+}
+
+pub fn handle_toggle_subcommand(
+    backend: &Backend,
+    options: &BackendOptions,
+    config: &Configuration,
+) -> Result<()> {
+    if ipc::send(&options.displays, &Request::Toggle)?.is_some() {
+        return Ok(());
     }
 
-    if let Some(value) = args.get_one::<Mode>("mode") {
-        state::write(value.clone())?;
+    require_persistent(backend)?;
+    let mut backend = backend.connect(options)?;
+    let displays = &options.displays;
+    let target = daemon::ToggleTarget::current(config, displays);
+    daemon::toggle_neutral(target, displays, &mut |temperature| {
+        let fade = &config.fade;
+        backend.fade_temperature(temperature, config, displays, None, fade, &mut |duration| {
+            thread::sleep(duration);
+            true
+        })
+    })?;
+    Ok(())
+}
+
+pub fn init_reset_subcommand() -> Command {
+    Command::new("reset")
+        .about("Restore a linear gamma ramp on all outputs and switch to static mode")
+}
+
+pub fn handle_reset_subcommand(backend: &Backend, options: &BackendOptions) -> Result<()> {
+    if ipc::send(&options.displays, &Request::Reset)?.is_some() {
         return Ok(());
     }
 
-    anyhow::bail!("No argument found")
+    // The compositor already restored the gamma of backends that aren't persistent
+    if !backend.capabilities().persistent {
+        return daemon::reset_state(&options.displays);
+    }
+
+    let mut backend = backend.connect(options)?;
+    daemon::reset_gamma(backend.as_mut(), &options.displays)
+}
+
+pub fn init_warmer_subcommand() -> Command {
+    Command::new("warmer")
+        .about("Lower the temperature by a step")
+        .arg(step_arg())
+}
+
+pub fn init_cooler_subcommand() -> Command {
+    Command::new("cooler")
+        .about("Raise the temperature by a step")
+        .arg(step_arg())
+}
+
+fn step_arg() -> Arg {
+    Arg::new("step")
+        .required(false)
+        .value_parser(value_parser!(f64))
+        .help("Step in Kelvin, defaults to `step` of the [adjust] section")
+}
+
+/// Handles both `warmer` and `cooler`, `direction` is -1 for warmer and 1 for cooler
+pub fn handle_step_subcommand(
+    args: &ArgMatches,
+    backend: &Backend,
+    options: &BackendOptions,
+    config: &Configuration,
+    direction: f64,
+) -> Result<()> {
+    let step = args
+        .get_one::<f64>("step")
+        .copied()
+        .unwrap_or(config.adjust.step);
+
+    let request = Request::AdjustTemperature {
+        delta: direction * step.abs(),
+        fade: None,
+    };
+    dispatch(request, backend, options, config)
 }
 
 pub fn init_daemon_subcommand() -> Command {
@@ -114,6 +776,7 @@ pub fn init_daemon_subcommand() -> Command {
 pub fn handle_daemon_subcommand(
     args: &ArgMatches,
     backend: &Backend,
+    options: &BackendOptions,
     config: &Configuration,
     sys: &mut sysinfo::System,
 ) -> Result<()> {
@@ -121,10 +784,13 @@ pub fn handle_daemon_subcommand(
 
     match args.subcommand() {
         Some(("start", args)) => {
-            daemon::start_daemon(args, config.clone(), backend, sys)?;
+            daemon::start_daemon(args, config.clone(), backend, options, sys)?;
         }
         Some(("stop", _)) => {
-            daemon::stop_daemon(sys)?;
+            // Fall back to signals for daemons that aren't listening on the socket
+            if ipc::send(&options.displays, &Request::Stop)?.is_none() {
+                daemon::stop_daemon(&options.displays, sys)?;
+            }
         }
         None | Some((_, _)) => anyhow::bail!("No subcommand provided"),
     }
@@ -137,17 +803,327 @@ pub fn init_list_subcommand() -> Command {
         .subcommand_required(true)
         .about("List various configured options")
         .subcommand(Command::new("presets").about("List all presets"))
+        .subcommand(Command::new("outputs").about("List all outputs of the backend"))
 }
 
-pub fn handle_list_subcommand(args: &ArgMatches, config: &Configuration) -> Result<()> {
+pub fn handle_list_subcommand(
+    args: &ArgMatches,
+    backend: &Backend,
+    options: &BackendOptions,
+    config: &Configuration,
+) -> Result<()> {
     match args.subcommand() {
+        Some(("presets", _)) if output_format(args) == Format::Json => {
+            print_json(&config.presets)?;
+        }
         Some(("presets", _)) => {
             for preset in config.presets.clone() {
                 println!("{}: {}K", preset.name, preset.temperature);
             }
         }
+        Some(("outputs", _)) if output_format(args) == Format::Json => {
+            #[derive(Serialize)]
+            struct OutputInfo {
+                #[serde(flatten)]
+                output: Output,
+                ramp_size: Option<usize>,
+            }
+
+            let mut backend = backend.connect(options)?;
+            let mut outputs = vec![];
+            for output in backend.outputs()? {
+                let ramp_size = match output.id {
+                    Some(_) => Some(backend.ramp_size(&output)?),
+                    None => None,
+                };
+                outputs.push(OutputInfo { output, ramp_size });
+            }
+            print_json(&outputs)?;
+        }
+        Some(("outputs", _)) => {
+            let mut backend = backend.connect(options)?;
+            for output in backend.outputs()? {
+                let connection = match output.connected {
+                    true => "connected",
+                    false => "disconnected",
+                };
+
+                match (output.id, output.crtc) {
+                    (Some(_), Some(crtc)) => println!(
+                        "{}: {}, crtc: {}, gamma size: {}",
+                        output.name,
+                        connection,
+                        crtc,
+                        backend.ramp_size(&output)?
+                    ),
+                    (Some(_), None) => println!(
+                        "{}: {}, gamma size: {}",
+                        output.name,
+                        connection,
+                        backend.ramp_size(&output)?
+                    ),
+                    (None, _) => println!("{}: {}, disabled", output.name, connection),
+                }
+            }
+        }
         None | Some((_, _)) => anyhow::bail!("No subcommand provided"),
     };
 
     Ok(())
 }
+
+pub fn init_config_subcommand() -> Command {
+    Command::new("config")
+        .subcommand_required(true)
+        .about("Inspect the configuration")
+        .subcommand(
+            Command::new("check")
+                .about("Validate a configuration file and print today's schedule")
+                .arg(
+                    Arg::new("path")
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Configuration file to check, defaults to the one in use"),
+                ),
+        )
+}
+
+/// Handled before the configuration is loaded, so errors in it can be reported here
+pub fn handle_config_subcommand(args: &ArgMatches, global_args: &ArgMatches) -> Result<()> {
+    match args.subcommand() {
+        Some(("check", args)) => {
+            let path = match args.get_one::<PathBuf>("path") {
+                Some(path) => path.clone(),
+                None => match Configuration::find_path(global_args)? {
+                    Some(path) => path,
+                    None => anyhow::bail!("No configuration file found"),
+                },
+            };
+
+            let config = Configuration::from_path(&path)?;
+            let problems = config.check();
+            let schedule = parse_schedule(&config);
+
+            if output_format(args) == Format::Json {
+                #[derive(Serialize)]
+                struct Check<'a> {
+                    path: &'a PathBuf,
+                    config: &'a Configuration,
+                    schedule: &'a [ScheduleBlock],
+                    problems: &'a [String],
+                }
+
+                print_json(&Check {
+                    path: &path,
+                    config: &config,
+                    schedule: &schedule,
+                    problems: &problems,
+                })?;
+                return match problems.len() {
+                    0 => Ok(()),
+                    count => anyhow::bail!("Found {count} problem(s) in {}", path.display()),
+                };
+            }
+
+            if schedule.is_empty() {
+                println!("No schedule entries");
+            } else {
+                println!("{:<7}{:<7}{:<13}Transition", "Start", "End", "Temperature");
+            }
+            for block in &schedule {
+                let transition = match block.transition_length().and_then(|t| t.to_std().ok()) {
+                    Some(transition) => humantime::format_duration(transition).to_string(),
+                    None => "-".into(),
+                };
+                println!(
+                    "{:<7}{:<7}{:<13}{}",
+                    block.start().format("%H:%M"),
+                    block.end().format("%H:%M"),
+                    format!("{}K", block.temperature()),
+                    transition
+                );
+            }
+
+            if !problems.is_empty() {
+                for problem in &problems {
+                    eprintln!("{}: {problem}", path.display());
+                }
+                anyhow::bail!("Found {} problem(s) in {}", problems.len(), path.display());
+            }
+
+            println!("{} is valid", path.display());
+        }
+        None | Some((_, _)) => anyhow::bail!("No subcommand provided"),
+    }
+
+    Ok(())
+}
+
+pub fn init_status_subcommand() -> Command {
+    Command::new("status")
+        .about("Print the temperature and mode for status bars, as waybar JSON with --format json")
+        .arg(
+            Arg::new("follow")
+                .short('f')
+                .long("follow")
+                .action(ArgAction::SetTrue)
+                .help("Keep running and print a line every time the status changes"),
+        )
+}
+
+/// Status shown in bars, serialized in the format of waybar's custom modules
+#[derive(Serialize, PartialEq)]
+struct BarStatus {
+    text: String,
+    alt: String,
+    tooltip: String,
+    class: Vec<String>,
+    /// Temperature relative to neutral
+    percentage: u8,
+}
+
+impl BarStatus {
+    fn new(backend: &str, mode: &Mode, temperature: Option<Temperature>, daemon: bool) -> Self {
+        let text = match temperature {
+            Some(temperature) => format!("{}K", temperature.as_f64().round()),
+            None => "-".into(),
+        };
+
+        let percentage = temperature.map_or(100.0, |temperature| {
+            let min = *Temperature::RANGE.start();
+            (temperature.as_f64() - min) / (Temperature::NEUTRAL - min) * 100.0
+        });
+
+        let mut class = vec![mode.to_string()];
+        if !daemon {
+            class.push("inactive".into());
+        }
+
+        Self {
+            tooltip: format!(
+                "Temperature: {text}\nMode: {mode}\nBackend: {backend}\nDaemon: {}",
+                match daemon {
+                    true => "active",
+                    false => "inactive",
+                }
+            ),
+            text,
+            alt: mode.to_string(),
+            class,
+            percentage: percentage.clamp(0.0, 100.0).round() as u8,
+        }
+    }
+
+    fn print(&self, format: Format) -> Result<()> {
+        match format {
+            Format::Json => print_json(self),
+            Format::Text => {
+                println!("{} ({})", self.text, self.alt);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How often `status --follow` checks whether a daemon was started
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+pub fn handle_status_subcommand(
+    args: &ArgMatches,
+    backend: &Backend,
+    options: &BackendOptions,
+    config: &Configuration,
+) -> Result<()> {
+    let format = output_format(args);
+    let local_status = || {
+        let mode = state::read::<Mode>(&options.displays).unwrap_or(config.mode.clone());
+        BarStatus::new(
+            &backend.to_string(),
+            &mode,
+            state::read(&options.displays),
+            false,
+        )
+    };
+
+    if !args.get_flag("follow") {
+        return match ipc::send(&options.displays, &Request::GetStatus)? {
+            Some(Response::Status(status)) => {
+                BarStatus::new(&status.backend, &status.mode, status.temperature, true)
+                    .print(format)
+            }
+            _ => local_status().print(format),
+        };
+    }
+
+    let mut printed = None;
+    let mut print = |status: BarStatus| -> Result<()> {
+        if printed.as_ref() != Some(&status) {
+            status.print(format)?;
+            printed = Some(status);
+        }
+        Ok(())
+    };
+
+    loop {
+        let subscribed = ipc::subscribe(&options.displays, |status| {
+            print(BarStatus::new(
+                &status.backend,
+                &status.mode,
+                status.temperature,
+                true,
+            ))
+        })?;
+
+        // Without a daemon nothing pushes updates, show what was applied last and wait for one
+        if !subscribed {
+            print(local_status())?;
+            thread::sleep(RECONNECT_INTERVAL);
+        }
+    }
+}
+
+pub fn init_cycle_subcommand() -> Command {
+    Command::new("cycle")
+        .about("Apply the next preset, e.g. when clicking a status bar module")
+        .arg(
+            Arg::new("reverse")
+                .short('r')
+                .long("reverse")
+                .action(ArgAction::SetTrue)
+                .help("Apply the previous preset instead"),
+        )
+}
+
+pub fn handle_cycle_subcommand(
+    args: &ArgMatches,
+    backend: &Backend,
+    options: &BackendOptions,
+    config: &Configuration,
+) -> Result<()> {
+    let presets = &config.presets;
+    if presets.is_empty() {
+        anyhow::bail!("No presets configured");
+    }
+
+    // Presets are matched by temperature, so cycling continues from presets set by the schedule
+    let current = state::read::<Temperature>(&options.displays).and_then(|temperature| {
+        presets
+            .iter()
+            .position(|p| p.temperature.as_f64().round() == temperature.as_f64().round())
+    });
+
+    let index = match (current, args.get_flag("reverse")) {
+        (Some(i), false) => (i + 1) % presets.len(),
+        (Some(i), true) => (i + presets.len() - 1) % presets.len(),
+        (None, _) => 0,
+    };
+
+    let request = Request::ApplyPreset {
+        preset: presets[index].name.clone(),
+        output: None,
+        fade: None,
+        brightness: None,
+        gamma: None,
+    };
+    dispatch(request, backend, options, config)
+}
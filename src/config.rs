@@ -1,16 +1,26 @@
-use std::{fmt::Display, path::PathBuf, str::FromStr, sync::OnceLock};
+use std::{
+    fmt::Display,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+    time::Duration,
+};
 
 use crate::{
-    backends::{Backend, Temperature},
+    backends::{Backend, Brightness, GammaCorrection, Temperature},
+    blackbody,
     utils::{self, RemoveSeconds},
 };
 use anyhow::Result;
 use bluegone::StateFileName;
 use chrono::{prelude as crono, DateTime};
 use clap::ArgMatches;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Configuration {
     #[serde(default)]
     pub backend: Backend,
@@ -20,6 +30,33 @@ pub struct Configuration {
     pub presets: Vec<Preset>,
     #[serde(default)]
     pub schedule: Vec<Schedule>,
+    #[serde(default)]
+    pub outputs: Vec<OutputConfig>,
+    #[serde(default)]
+    pub fade: Fade,
+    /// Blend linearly between consecutive schedule entries instead of switching at the trigger
+    #[serde(default)]
+    pub interpolate: bool,
+    #[serde(default)]
+    pub adjust: Adjust,
+    /// X displays to connect to, e.g. `[":0", ":1"]`, `$DISPLAY` is used when empty
+    #[serde(default)]
+    pub display: Vec<String>,
+    /// Virtual console of the console backend, e.g. `/dev/tty2`, `/dev/tty0` is used when unset
+    #[serde(default)]
+    pub console: Option<PathBuf>,
+    /// Card of the drm backend, e.g. `/dev/dri/card1`, the first one with CRTCs is used when unset
+    #[serde(default)]
+    pub drm_card: Option<PathBuf>,
+    /// How temperatures are turned into colors
+    #[serde(default)]
+    pub color_algorithm: ColorAlgorithm,
+    /// Exponent of the red, green and blue ramps, used until a preset or `set --gamma` changes it
+    #[serde(default)]
+    pub gamma: GammaCorrection,
+    /// File the configuration was loaded from
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
 }
 
 static CONFIG: OnceLock<Configuration> = OnceLock::new();
@@ -30,21 +67,45 @@ static CONFIG_PATHS: [&str; 3] = [
 ];
 
 impl Configuration {
-    fn from_str(content: &str) -> Result<Self> {
-        let config = match toml::from_str::<Self>(content) {
-            Ok(config) => config,
-            Err(err) => {
-                eprintln!("Error parsing config file: {}", err);
-                return Ok(Configuration::default());
-            }
-        };
+    fn from_str(content: &str, path: Option<&Path>) -> Result<Self> {
+        let config = toml::from_str::<Self>(content)
+            .map_err(|err| diagnostic(path, content, err.span(), err.message()))?;
+
+        config
+            .validate()
+            .map_err(|(span, message)| diagnostic(path, content, Some(span), &message))?;
+
+        Ok(config)
+    }
 
+    pub fn from_path(path: &PathBuf) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("Unable to read {}: {err}", path.display()))?;
+        let mut config = Self::from_str(&content, Some(path))?;
+        // The daemon changes its working directory when running in the background
+        config.path = Some(std::path::absolute(path)?);
         Ok(config)
     }
 
-    fn from_path(path: &PathBuf) -> Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        Self::from_str(&content)
+    /// Checks references between sections that can't be verified while deserializing,
+    /// returns the location of the offending value along with an error message.
+    fn validate(&self) -> std::result::Result<(), (Range<usize>, String)> {
+        for schedule in &self.schedule {
+            let trigger = schedule.get_spanned_trigger();
+            if let (ScheduleTrigger::Light(_), None) = (trigger.get_ref(), &self.location) {
+                let message = "Sunrise and sunset triggers require a [location] section".into();
+                return Err((trigger.span(), message));
+            }
+
+            if let Schedule::Preset { preset, .. } = schedule {
+                if !self.presets.iter().any(|p| p.name == *preset.get_ref()) {
+                    let message = format!("`preset = \"{}\"` does not exist", preset.get_ref());
+                    return Err((preset.span(), message));
+                }
+            }
+        }
+
+        Ok(())
     }
 
     pub fn get_config(args: &ArgMatches) -> Result<&Self> {
@@ -52,34 +113,174 @@ impl Configuration {
             return Ok(data);
         }
 
-        match args.get_one::<PathBuf>("config") {
-            Some(path) => {
-                let data = Self::from_path(path)?;
-                CONFIG.set(data).expect("OnceLock to be unlocked");
-            }
+        let data = match Self::find_path(args)? {
+            Some(path) => Self::from_path(&path)?,
             None => {
-                for path in CONFIG_PATHS.iter() {
-                    let path = match path.strip_prefix("~/") {
-                        Some(path) => utils::home_dir().join(path),
-                        None => PathBuf::from_str(path)?,
-                    };
-
-                    if std::fs::metadata(&path).is_err() {
-                        continue;
-                    }
-
-                    let data = Self::from_path(&path)?;
-                    CONFIG.set(data).expect("OnceLock to be unlocked");
-                    break;
-                }
+                log::debug!("No configuration file found, using defaults");
+                Self::default()
             }
         };
+        CONFIG.set(data).expect("OnceLock to be unlocked");
 
         Ok(CONFIG.get().expect("Config to be set"))
     }
+
+    /// Path given with `--config`, otherwise the first existing file of `CONFIG_PATHS`
+    pub fn find_path(args: &ArgMatches) -> Result<Option<PathBuf>> {
+        if let Some(path) = args.get_one::<PathBuf>("config") {
+            return Ok(Some(path.clone()));
+        }
+
+        for path in CONFIG_PATHS.iter() {
+            let path = match path.strip_prefix("~/") {
+                Some(path) => utils::home_dir().join(path),
+                None => PathBuf::from_str(path)?,
+            };
+
+            if std::fs::metadata(&path).is_ok() {
+                return Ok(Some(path));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Finds likely mistakes that don't prevent the configuration from loading
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = vec![];
+        let range = Temperature::RANGE;
+        let in_range = |temperature: &Temperature| range.contains(&temperature.as_f64());
+
+        for (i, preset) in self.presets.iter().enumerate() {
+            if self.presets[..i].iter().any(|p| p.name == preset.name) {
+                problems.push(format!(
+                    "Preset `{}` is defined more than once",
+                    preset.name
+                ));
+            }
+            if !in_range(&preset.temperature) {
+                problems.push(format!(
+                    "Preset `{}` has a temperature of {}K, expected {}-{}K",
+                    preset.name,
+                    preset.temperature,
+                    range.start(),
+                    range.end()
+                ));
+            }
+        }
+
+        for output in &self.outputs {
+            if let Some(temperature) = output.temperature.filter(|t| !in_range(t)) {
+                problems.push(format!(
+                    "Output `{}` has a temperature of {}K, expected {}-{}K",
+                    output.name,
+                    temperature,
+                    range.start(),
+                    range.end()
+                ));
+            }
+        }
+
+        let adjust = &self.adjust;
+        if adjust.min.as_f64() > adjust.max.as_f64() {
+            problems.push(format!(
+                "adjust.min ({}K) is greater than adjust.max ({}K)",
+                adjust.min, adjust.max
+            ));
+        }
+        for (key, temperature) in [("adjust.min", adjust.min), ("adjust.max", adjust.max)] {
+            if !in_range(&temperature) {
+                problems.push(format!(
+                    "{key} is {}K, expected {}-{}K",
+                    temperature,
+                    range.start(),
+                    range.end()
+                ));
+            }
+        }
+
+        let mut times: Vec<(usize, chrono::NaiveTime)> = vec![];
+        for (i, schedule) in self.schedule.iter().enumerate() {
+            let trigger = schedule.get_trigger();
+            if let Schedule::Temperature { temperature, .. } = schedule {
+                if !in_range(temperature) {
+                    problems.push(format!(
+                        "Schedule entry #{} (`{}`) has a temperature of {}K, expected {}-{}K",
+                        i + 1,
+                        trigger,
+                        temperature,
+                        range.start(),
+                        range.end()
+                    ));
+                }
+            }
+
+            let time = match schedule.get_time(&self.location) {
+                Ok(time) => time,
+                Err(err) => {
+                    problems.push(format!(
+                        "Schedule entry #{} (`{}`) can't be resolved: {err}",
+                        i + 1,
+                        trigger
+                    ));
+                    continue;
+                }
+            };
+
+            if let Some(&(other, _)) = times.iter().find(|&&(_, t)| t == time) {
+                problems.push(format!(
+                    "Schedule entries #{} (`{}`) and #{} (`{}`) both trigger at {}, only #{} is used",
+                    other + 1,
+                    self.schedule[other].get_trigger(),
+                    i + 1,
+                    trigger,
+                    time.format("%H:%M"),
+                    i + 1
+                ));
+            }
+            times.push((i, time));
+        }
+
+        problems
+    }
+
+    /// Temperature for a specific output after applying its override or offset
+    pub fn output_temperature(&self, output: &str, temperature: Temperature) -> Temperature {
+        match self.outputs.iter().find(|o| o.name == output) {
+            Some(OutputConfig {
+                temperature: Some(temperature),
+                ..
+            }) => *temperature,
+            Some(OutputConfig { offset, .. }) => Temperature::new(temperature.as_f64() + offset),
+            None => temperature,
+        }
+    }
 }
 
-#[derive(Deserialize, Debug, clap::ValueEnum, Default, Clone, PartialEq)]
+/// Formats an error pointing at the line and column of `span` in the configuration file
+fn diagnostic(
+    path: Option<&Path>,
+    content: &str,
+    span: Option<Range<usize>>,
+    message: &str,
+) -> anyhow::Error {
+    let file = match path {
+        Some(path) => path.display().to_string(),
+        None => "configuration".into(),
+    };
+
+    match span {
+        Some(span) => {
+            let before = &content[..span.start.min(content.len())];
+            let line = before.matches('\n').count() + 1;
+            let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+            anyhow::anyhow!("{file}:{line}:{column}: {message}")
+        }
+        None => anyhow::anyhow!("{file}: {message}"),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, clap::ValueEnum, Default, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum Mode {
     #[default]
@@ -112,24 +313,90 @@ impl StateFileName for Mode {
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Preset {
     pub name: String,
     pub temperature: Temperature,
+    pub brightness: Option<Brightness>,
+    pub gamma: Option<GammaCorrection>,
 }
 
-#[derive(Debug, Clone)]
+/// Per output settings, matched by the output name (e.g. DP-1, or :1/DP-1 with several displays)
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OutputConfig {
+    pub name: String,
+    /// Added to the temperature of the schedule or preset
+    #[serde(default)]
+    pub offset: f64,
+    /// Used instead of the temperature of the schedule or preset, takes precedence over `offset`
+    pub temperature: Option<Temperature>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(try_from = "RawSchedule", untagged)]
 pub enum Schedule {
     Temperature {
-        trigger: ScheduleTrigger,
+        trigger: Spanned<ScheduleTrigger>,
         temperature: Temperature,
+        brightness: Option<Brightness>,
+        #[serde(serialize_with = "serialize_optional_duration")]
+        transition: Option<Duration>,
     },
     Preset {
-        trigger: ScheduleTrigger,
-        preset: String,
+        trigger: Spanned<ScheduleTrigger>,
+        preset: Spanned<String>,
+        /// Takes precedence over the brightness of the preset
+        brightness: Option<Brightness>,
+        #[serde(serialize_with = "serialize_optional_duration")]
+        transition: Option<Duration>,
     },
 }
 
+/// Schedule entry as written in the configuration file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSchedule {
+    trigger: Spanned<ScheduleTrigger>,
+    temperature: Option<Temperature>,
+    preset: Option<Spanned<String>>,
+    brightness: Option<Brightness>,
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    transition: Option<Duration>,
+}
+
+impl TryFrom<RawSchedule> for Schedule {
+    type Error = String;
+
+    fn try_from(value: RawSchedule) -> std::result::Result<Self, Self::Error> {
+        let RawSchedule {
+            trigger,
+            temperature,
+            preset,
+            brightness,
+            transition,
+        } = value;
+
+        match (temperature, preset) {
+            (Some(_), Some(_)) => Err("Cannot have both `temperature` and `preset` fields".into()),
+            (None, None) => Err("Must have either a `temperature` or `preset` field".into()),
+            (Some(temperature), None) => Ok(Schedule::Temperature {
+                trigger,
+                temperature,
+                brightness,
+                transition,
+            }),
+            (None, Some(preset)) => Ok(Schedule::Preset {
+                trigger,
+                preset,
+                brightness,
+                transition,
+            }),
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub enum ScheduleLightTrigger {
     Sunset,
@@ -167,27 +434,181 @@ impl Default for Configuration {
             backend: Backend::default(),
             mode: Mode::default(),
             schedule: vec![],
+            outputs: vec![],
+            fade: Fade::default(),
+            interpolate: false,
+            adjust: Adjust::default(),
+            display: vec![],
+            console: None,
+            drm_card: None,
+            color_algorithm: ColorAlgorithm::default(),
+            gamma: GammaCorrection::default(),
+            path: None,
             presets: vec![
                 Preset {
                     name: "day".to_string(),
                     temperature: Temperature::new(6500.0),
+                    brightness: None,
+                    gamma: None,
                 },
                 Preset {
                     name: "night".to_string(),
                     temperature: Temperature::new(4000.0),
+                    brightness: None,
+                    gamma: None,
                 },
             ],
         }
     }
 }
 
+/// Step size and bounds of relative temperature changes, e.g. `set -t +500` or `warmer`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct Adjust {
+    pub step: f64,
+    pub min: Temperature,
+    pub max: Temperature,
+}
+
+impl Adjust {
+    pub fn clamp(&self, temperature: f64) -> Temperature {
+        Temperature::new(temperature.clamp(self.min.as_f64(), self.max.as_f64()))
+    }
+}
+
+impl Default for Adjust {
+    fn default() -> Self {
+        Self {
+            step: 250.0,
+            min: Temperature::new(*Temperature::RANGE.start()),
+            max: Temperature::new(Temperature::NEUTRAL),
+        }
+    }
+}
+
+/// Time between two steps of a fade
+pub const FADE_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Fade {
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration"
+    )]
+    pub duration: Duration,
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+impl Fade {
+    pub fn new(duration: Duration, easing: Easing) -> Self {
+        Self { duration, easing }
+    }
+
+    /// Intermediate temperatures when fading from `from` to `to`, excluding both ends.
+    pub fn steps(&self, from: Temperature, to: Temperature) -> Vec<Temperature> {
+        let count = self.duration.as_millis() / FADE_INTERVAL.as_millis();
+        if count < 2 || from.as_f64() == to.as_f64() {
+            return vec![];
+        }
+
+        (1..count)
+            .map(|i| {
+                let progress = self.easing.apply(i as f64 / count as f64);
+                Temperature::new(from.as_f64() + (to.as_f64() - from.as_f64()) * progress)
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps linear progress between 0 and 1 onto the easing curve
+    pub fn apply(&self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorAlgorithm {
+    /// Curve fit by Tanner Helland, drifts off below 2000K
+    #[default]
+    TannerHelland,
+    /// Table of the blackbody locus, matches redshift and gammastep
+    Blackbody,
+}
+
+impl ColorAlgorithm {
+    /// Gamma values between 0 and 1 of the temperature in Kelvin
+    pub fn temp_to_gamma(&self, temp: f64) -> (f64, f64, f64) {
+        match self {
+            ColorAlgorithm::TannerHelland => utils::temp_to_gamma(temp),
+            ColorAlgorithm::Blackbody => blackbody::temp_to_gamma(temp),
+        }
+    }
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    humantime::parse_duration(&s).map_err(serde::de::Error::custom)
+}
+
+fn serialize_duration<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_str(&humantime::format_duration(*duration))
+}
+
+pub fn serialize_optional_duration<S>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match duration {
+        Some(duration) => serialize_duration(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn deserialize_optional_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_duration(deserializer).map(Some)
+}
+
 #[derive(Debug, Clone)]
 pub enum ScheduleTrigger {
     Time(crono::NaiveTime),
     Light(ScheduleLightTrigger),
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Location {
     pub latitude: f64,
     pub longitude: f64,
@@ -203,18 +624,76 @@ impl Schedule {
             Schedule::Preset { preset, .. } => {
                 presets
                     .iter()
-                    .find(|p| p.name == *preset)
-                    .expect("preset to exist")
+                    .find(|p| p.name == *preset.get_ref())
+                    .expect("presets to be validated when loading the configuration")
                     .temperature
             }
         }
     }
+    /// Name of the preset the temperature comes from
+    pub fn get_preset(&self) -> Option<&str> {
+        match self {
+            Schedule::Temperature { .. } => None,
+            Schedule::Preset { preset, .. } => Some(preset.get_ref()),
+        }
+    }
     pub fn get_trigger(&self) -> &ScheduleTrigger {
+        self.get_spanned_trigger().get_ref()
+    }
+    fn get_spanned_trigger(&self) -> &Spanned<ScheduleTrigger> {
         match self {
             Schedule::Temperature { trigger, .. } => trigger,
             Schedule::Preset { trigger, .. } => trigger,
         }
     }
+    /// Brightness of the entry, or of its preset, `None` leaves the brightness as it is
+    pub fn get_brightness(&self, presets: &[Preset]) -> Option<Brightness> {
+        match self {
+            Schedule::Temperature { brightness, .. } => *brightness,
+            Schedule::Preset {
+                preset, brightness, ..
+            } => brightness.or_else(|| {
+                presets
+                    .iter()
+                    .find(|p| p.name == *preset.get_ref())
+                    .and_then(|p| p.brightness)
+            }),
+        }
+    }
+    /// Gamma correction of the preset the entry refers to
+    pub fn get_gamma(&self, presets: &[Preset]) -> Option<GammaCorrection> {
+        let preset = self.get_preset()?;
+        presets
+            .iter()
+            .find(|p| p.name == preset)
+            .and_then(|p| p.gamma)
+    }
+    /// How long before the trigger to start blending from the previous entry
+    pub fn get_transition(&self) -> Option<Duration> {
+        match self {
+            Schedule::Temperature { transition, .. } => *transition,
+            Schedule::Preset { transition, .. } => *transition,
+        }
+    }
+}
+
+impl Display for ScheduleTrigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScheduleTrigger::Time(time) => write!(f, "{}", time.format("%H:%M")),
+            ScheduleTrigger::Light(ScheduleLightTrigger::Sunset) => write!(f, "sunset"),
+            ScheduleTrigger::Light(ScheduleLightTrigger::Sunrise) => write!(f, "sunrise"),
+        }
+    }
+}
+
+impl Serialize for ScheduleTrigger {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
 }
 
 impl ScheduleTrigger {
@@ -224,7 +703,7 @@ impl ScheduleTrigger {
             ScheduleTrigger::Light(state) => state.get_time(
                 location
                     .clone()
-                    .expect("Location should be set, unreachable state."),
+                    .expect("location to be validated when loading the configuration"),
             ),
         }
     }
@@ -243,54 +722,192 @@ impl<'de> Deserialize<'de> for ScheduleTrigger {
             if let Ok(t) = parsed_time {
                 return Ok(ScheduleTrigger::Time(t));
             }
-            return Err(serde::de::Error::custom("Invalid time format"));
+            return Err(serde::de::Error::custom(format!("Invalid time `{s}`")));
         }
 
         match s.as_str() {
             "sunset" => Ok(ScheduleTrigger::Light(ScheduleLightTrigger::Sunset)),
             "sunrise" => Ok(ScheduleTrigger::Light(ScheduleLightTrigger::Sunrise)),
-            _ => Err(serde::de::Error::custom("Invalid trigger")),
+            _ => Err(serde::de::Error::custom(format!(
+                "Invalid trigger `{s}`, expected a time (HH:MM), sunrise or sunset"
+            ))),
         }
     }
 }
 
-impl<'a> Deserialize<'a> for Schedule {
-    fn deserialize<D>(deserializer: D) -> Result<Schedule, D::Error>
-    where
-        D: serde::Deserializer<'a>,
-    {
-        let value = toml::Value::deserialize(deserializer)?;
-        let table = value.as_table().unwrap();
-        // deserialize trigger field
-        let trigger = table.get("trigger").unwrap();
-        let trigger = ScheduleTrigger::deserialize(trigger.clone()).unwrap();
-
-        let has_temperature = table.contains_key("temperature");
-        let has_preset = table.contains_key("preset");
-
-        match (has_temperature, has_preset) {
-            (true, true) => Err(serde::de::Error::custom(
-                "Cannot have both temperature and preset fields",
-            )),
-            (false, false) => Err(serde::de::Error::custom(
-                "Must have either temperature or preset field",
-            )),
-            (true, false) => {
-                let temperature = table.get("temperature").unwrap();
-                let temperature = Temperature::deserialize(temperature.clone()).unwrap();
-                Ok(Schedule::Temperature {
-                    trigger: trigger.clone(),
-                    temperature,
-                })
-            }
-            (false, true) => {
-                let preset = table.get("preset").unwrap();
-                let preset = preset.as_str().unwrap();
-                Ok(Schedule::Preset {
-                    trigger: trigger.clone(),
-                    preset: preset.to_string(),
-                })
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(content: &str) -> String {
+        Configuration::from_str(content, Some(Path::new("bluegone.toml")))
+            .expect_err("an invalid configuration")
+            .to_string()
+    }
+
+    #[test]
+    fn reports_unknown_preset() {
+        let content = "presets = []\n\n[[schedule]]\ntrigger = \"07:00\"\npreset = \"day\"\n";
+        assert_eq!(
+            error(content),
+            "bluegone.toml:5:10: `preset = \"day\"` does not exist"
+        );
+    }
+
+    #[test]
+    fn reports_sunrise_without_location() {
+        let content = "presets = []\n\n[[schedule]]\ntrigger = \"sunrise\"\ntemperature = 4000\n";
+        assert_eq!(
+            error(content),
+            "bluegone.toml:4:11: Sunrise and sunset triggers require a [location] section"
+        );
+    }
+
+    #[test]
+    fn reports_missing_temperature() {
+        let content = "presets = []\n\n[[schedule]]\ntrigger = \"07:00\"\n";
+        assert_eq!(
+            error(content),
+            "bluegone.toml:3:1: Must have either a `temperature` or `preset` field"
+        );
+    }
+
+    #[test]
+    fn reports_invalid_time() {
+        let content = "presets = []\n\n[[schedule]]\ntrigger = \"25:00\"\ntemperature = 4000\n";
+        assert_eq!(error(content), "bluegone.toml:4:11: Invalid time `25:00`");
+    }
+
+    #[test]
+    fn reports_out_of_range_brightness() {
+        let content = "[[presets]]\nname = \"day\"\ntemperature = 6500\nbrightness = 2.0\n";
+        assert_eq!(
+            error(content),
+            "bluegone.toml:4:14: Brightness must be between 0.1 and 1.0, got 2"
+        );
+    }
+
+    fn problems(content: &str) -> Vec<String> {
+        Configuration::from_str(content, None)
+            .expect("a valid configuration")
+            .check()
+    }
+
+    #[test]
+    fn check_accepts_valid_configuration() {
+        let content = r#"
+            [[presets]]
+            name = "day"
+            temperature = 6500
+
+            [[schedule]]
+            trigger = "07:00"
+            preset = "day"
+
+            [[schedule]]
+            trigger = "21:00"
+            temperature = 4000
+        "#;
+        assert!(problems(content).is_empty());
+    }
+
+    #[test]
+    fn check_reports_duplicate_presets() {
+        let content = r#"
+            [[presets]]
+            name = "day"
+            temperature = 6500
+
+            [[presets]]
+            name = "day"
+            temperature = 5500
+        "#;
+        assert_eq!(
+            problems(content),
+            ["Preset `day` is defined more than once"]
+        );
+    }
+
+    #[test]
+    fn check_reports_same_time_triggers() {
+        let content = r#"
+            presets = []
+
+            [[schedule]]
+            trigger = "07:00"
+            temperature = 6500
+
+            [[schedule]]
+            trigger = "21:00"
+            temperature = 4000
+
+            [[schedule]]
+            trigger = "07:00"
+            temperature = 5500
+        "#;
+        assert_eq!(
+            problems(content),
+            ["Schedule entries #1 (`07:00`) and #3 (`07:00`) both trigger at 07:00, only #3 is used"]
+        );
+    }
+
+    #[test]
+    fn check_reports_out_of_range_temperatures() {
+        let content = r#"
+            [[presets]]
+            name = "hot"
+            temperature = 50000
+
+            [[outputs]]
+            name = "DP-1"
+            temperature = 500
+
+            [adjust]
+            step = 250
+            min = 3000
+            max = 2000
+
+            [[schedule]]
+            trigger = "07:00"
+            temperature = 900
+        "#;
+        assert_eq!(
+            problems(content),
+            [
+                "Preset `hot` has a temperature of 50000K, expected 1000-40000K",
+                "Output `DP-1` has a temperature of 500K, expected 1000-40000K",
+                "adjust.min (3000K) is greater than adjust.max (2000K)",
+                "Schedule entry #1 (`07:00`) has a temperature of 900K, expected 1000-40000K",
+            ]
+        );
+    }
+
+    #[test]
+    fn check_reports_adjust_limits_out_of_range() {
+        let content = r#"
+            presets = []
+
+            [adjust]
+            step = 250
+            min = 500
+            max = 45000
+        "#;
+        assert_eq!(
+            problems(content),
+            [
+                "adjust.min is 500K, expected 1000-40000K",
+                "adjust.max is 45000K, expected 1000-40000K",
+            ]
+        );
+    }
+
+    #[test]
+    fn names_configuration_without_path() {
+        let content = "presets = []\n\n[[schedule]]\ntrigger = \"25:00\"\ntemperature = 4000\n";
+        let error = Configuration::from_str(content, None).expect_err("an invalid time");
+        assert_eq!(
+            error.to_string(),
+            "configuration:4:11: Invalid time `25:00`"
+        );
     }
 }
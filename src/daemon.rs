@@ -1,33 +1,52 @@
 use crate::{
-    backends::{Backend, Temperature},
-    config::{self, Configuration, Mode},
+    backends::{
+        self, Backend, BackendOptions, Brightness, GammaBackend, GammaCorrection, Temperature,
+    },
+    config::{self, Configuration, Fade, Mode, Schedule, ScheduleTrigger},
+    ipc::{self, Request, Response, Status},
     state,
-    utils::{self, RemoveSeconds},
+    utils::{self},
 };
 use anyhow::Result;
-use bluegone::Pid;
-use chrono::{NaiveTime, Timelike};
+use bluegone::{Pid, StateFileName};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
 use clap::ArgMatches;
 use daemonize_me::Daemon;
-use signal_hook::{consts::{SIGINT, SIGTERM}, iterator::Signals};
-use std::thread;
+use inotify::{Inotify, WatchMask};
+use signal_hook::{consts::{SIGHUP, SIGINT, SIGTERM}, iterator::Signals};
+use std::{
+    fmt::Display,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use serde::Serialize;
 use sysinfo::System;
 
 pub fn start_daemon(
     args: &ArgMatches,
     config: Configuration,
     backend: &Backend,
+    options: &BackendOptions,
     sys: &mut System,
 ) -> Result<()> {
     if config.mode == config::Mode::Static {
         anyhow::bail!("Static mode is not supported in the daemon.");
     }
 
+    let displays = &options.displays;
+
     // If there is a lingering pid file we check if that process is still running
     // if not we can delete it and continue
-    if let Some(pid) = state::read::<Pid>() {
+    if let Some(pid) = state::read::<Pid>(displays) {
         match find_process_by_id(pid, sys) {
-            None => state::delete::<Pid>(),
+            None => state::delete::<Pid>(displays),
             Some(_) => anyhow::bail!("Daemon already running."),
         }?;
     }
@@ -36,30 +55,89 @@ pub fn start_daemon(
         .target(env_logger::Target::Stdout)
         .init();
 
-    match args.get_one::<bool>("background") {
-        Some(true) => {
-            let log_file = utils::new_log_file()?;
-            Daemon::new()
-                .pid_file(state::file_path::<Pid>(), Some(false))
-                .stdout(log_file)
-                .start()?
+    if let Some(true) = args.get_one::<bool>("background") {
+        let log_file = utils::new_log_file()?;
+        Daemon::new().stdout(log_file).start()?
+    }
+    // Written for every display, so the daemon is found through any of them
+    let pid: Pid = std::process::id().into();
+    state::write(displays, pid)?;
+
+    let backend_name = backend.to_string();
+    let mut backend = backend.connect(options)?;
+    let (sender, receiver) = mpsc::channel();
+    let outputs_sender = sender.clone();
+    backend.watch_outputs(Arc::new(move |outputs| {
+        let _ = outputs_sender.send(DaemonEvent::OutputsChanged(outputs));
+    }))?;
+    spawn_signal_handler(sender.clone())?;
+    spawn_clock_watcher(sender.clone())?;
+    if let Some(path) = &config.path {
+        spawn_config_watcher(path, sender.clone())?;
+    }
+    ipc::spawn_listener(displays, sender)?;
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        EventLoop {
+            config,
+            options: options.clone(),
+            backend: backend.as_mut(),
+            backend_name,
+            receiver,
+            pending: None,
+            interrupted: None,
+            toggling: None,
+            schedule: vec![],
+            schedule_date: None,
+            applied: None,
+            applied_brightness: None,
+            applied_gamma: None,
+            offset: None,
+            subscribers: vec![],
+            notified: None,
         }
-        _ => {
-            let pid: Pid = std::process::id().into();
-            state::write(pid)?;
+        .run()
+    }));
+
+    // Whatever happened, never leave the screen tinted after the daemon is gone
+    log::info!("Restoring original gamma");
+    match backend.restore() {
+        // The screen no longer shows what the state files describe
+        Ok(()) => {
+            if let Err(err) = clear_applied_state(displays) {
+                log::warn!("Unable to clear applied temperature: {err}");
+            }
         }
+        Err(err) => log::error!("Unable to restore original gamma: {err}"),
+    }
+    if let Err(err) = state::delete::<Pid>(displays) {
+        log::warn!("Unable to remove pid file: {err}");
+    }
+    if let Err(err) = ipc::remove_sockets(displays) {
+        log::warn!("Unable to remove socket: {err}");
     }
 
-    spawn_signal_handler()?;
-    start_event_loop(&config, backend)?;
+    match result {
+        Ok(result) => result,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
 
-    Ok(())
+/// Forgets the temperature, brightness and gamma correction that were applied last
+fn clear_applied_state(displays: &[String]) -> Result<()> {
+    state::clear::<Temperature>(displays)?;
+    state::clear::<Brightness>(displays)?;
+    state::clear::<GammaCorrection>(displays)
 }
 
-pub fn stop_daemon(sys: &mut System) -> Result<()> {
-    match state::read::<Pid>() {
+pub fn stop_daemon(displays: &[String], sys: &mut System) -> Result<()> {
+    match state::read::<Pid>(displays) {
+        // SIGTERM gives the daemon a chance to restore the original gamma before exiting
         Some(pid) => match find_process_by_id(pid, sys) {
-            Some(process) => process.kill(),
+            Some(process) => match process.kill_with(sysinfo::Signal::Term) {
+                Some(sent) => sent,
+                None => process.kill(),
+            },
             None => anyhow::bail!("No active daemon found."),
         },
         None => anyhow::bail!("No active daemon found."),
@@ -73,110 +151,1281 @@ pub fn find_process_by_id(pid: Pid, sys: &mut System) -> Option<&sysinfo::Proces
     sys.processes().get(pid_state)
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Temperature that replaces the schedule until `until`, e.g. `set -t 6500 --for 1h`
+#[derive(Debug, Clone, Serialize)]
+pub struct Override {
+    pub temperature: Temperature,
+    #[serde(serialize_with = "serialize_date_time")]
+    pub until: DateTime<Local>,
+}
+
+impl Override {
+    /// Time left before the schedule resumes, `None` once it expired
+    pub fn remaining(&self) -> Option<chrono::Duration> {
+        let remaining = self.until - Local::now();
+        (remaining > chrono::Duration::zero()).then_some(remaining)
+    }
+}
+
+impl Display for Override {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.temperature, self.until.to_rfc3339())
+    }
+}
+
+impl TryFrom<String> for Override {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (temperature, until) = match value.trim().split_once(' ') {
+            Some(parts) => parts,
+            None => anyhow::bail!("Invalid override: {value}"),
+        };
+
+        Ok(Self {
+            temperature: Temperature::try_from(temperature.to_string())?,
+            until: DateTime::parse_from_rfc3339(until)?.with_timezone(&Local),
+        })
+    }
+}
+
+impl StateFileName for Override {
+    fn name() -> String {
+        "override".into()
+    }
+}
+
+fn serialize_date_time<S>(time: &DateTime<Local>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_str(&time.to_rfc3339())
+}
+
+/// Mode and temperature that were active before `toggle` switched to a neutral temperature
+#[derive(Debug, Clone)]
+pub struct Toggled {
+    pub mode: Mode,
+    pub temperature: Temperature,
+}
+
+impl Toggled {
+    /// Remembers the current mode and temperature
+    pub fn current(config: &Configuration, displays: &[String]) -> Self {
+        Self {
+            mode: state::read(displays).unwrap_or(config.mode.clone()),
+            temperature: state::read(displays).unwrap_or(Temperature::new(Temperature::NEUTRAL)),
+        }
+    }
+}
+
+impl Display for Toggled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.mode, self.temperature)
+    }
+}
+
+impl TryFrom<String> for Toggled {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (mode, temperature) = match value.trim().split_once(' ') {
+            Some(parts) => parts,
+            None => anyhow::bail!("Invalid toggle state: {value}"),
+        };
+
+        Ok(Self {
+            mode: Mode::try_from(mode.to_string())?,
+            temperature: Temperature::try_from(temperature.to_string())?,
+        })
+    }
+}
+
+impl StateFileName for Toggled {
+    fn name() -> String {
+        "toggled".into()
+    }
+}
+
+/// Where a toggle is heading, see `toggle_neutral`
+#[derive(Debug, Clone)]
+pub enum ToggleTarget {
+    /// Fades to a neutral temperature and remembers what was active before
+    Neutral(Toggled),
+    /// Fades back to the temperature and mode that were active before
+    Back(Toggled, Temperature),
+}
+
+impl ToggleTarget {
+    /// Neutral unless a toggle is active, in which case it goes back to what was active before
+    pub fn current(config: &Configuration, displays: &[String]) -> Self {
+        let previous = match state::read::<Toggled>(displays) {
+            Some(previous) => previous,
+            None => return Self::Neutral(Toggled::current(config, displays)),
+        };
+
+        let temperature = match previous.mode {
+            Mode::Static => previous.temperature,
+            // Go straight to what the schedule wants right now, it takes over from there
+            Mode::Dynamic => {
+                match get_current_schedule(&parse_schedule(config), Local::now().time()) {
+                    Some((_, temperature)) => temperature,
+                    None => previous.temperature,
+                }
+            }
+        };
+        Self::Back(previous, temperature)
+    }
+
+    pub fn temperature(&self) -> Temperature {
+        match self {
+            Self::Neutral(_) => Temperature::new(Temperature::NEUTRAL),
+            Self::Back(_, temperature) => *temperature,
+        }
+    }
+
+    /// Writes the mode and toggle state once the screen reached `temperature`
+    fn finish(self, displays: &[String]) -> Result<()> {
+        match self {
+            Self::Neutral(previous) => {
+                state::clear::<Override>(displays)?;
+                state::write(displays, Mode::Static)?;
+                state::write(displays, previous)
+            }
+            Self::Back(previous, _) => {
+                state::clear::<Toggled>(displays)?;
+                state::write(displays, previous.mode)
+            }
+        }
+    }
+}
+
+/// Switches between a neutral temperature and whatever was active before, `fade` moves the
+/// screen to a temperature and returns false when it was interrupted. Nothing is written in
+/// that case. Used by the daemon and the cli alike, so both toggle the same way.
+pub fn toggle_neutral(
+    target: ToggleTarget,
+    displays: &[String],
+    fade: &mut dyn FnMut(Temperature) -> Result<bool>,
+) -> Result<bool> {
+    if !fade(target.temperature())? {
+        return Ok(false);
+    }
+
+    target.finish(displays)?;
+    Ok(true)
+}
+
+/// Puts a linear ramp on every output, switches to static mode and forgets the brightness,
+/// gamma correction, override and toggle that were set.
+pub fn reset_gamma(backend: &mut dyn GammaBackend, displays: &[String]) -> Result<()> {
+    backend.reset()?;
+    reset_state(displays)
+}
+
+/// State left behind by `reset_gamma`, a linear ramp in static mode
+pub fn reset_state(displays: &[String]) -> Result<()> {
+    state::write(displays, Mode::Static)?;
+    state::write(displays, Temperature::new(Temperature::NEUTRAL))?;
+    state::clear::<Brightness>(displays)?;
+    state::clear::<GammaCorrection>(displays)?;
+    state::clear::<Override>(displays)?;
+    state::clear::<Toggled>(displays)
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct ScheduleBlock {
+    #[serde(serialize_with = "serialize_time")]
     start: NaiveTime,
+    #[serde(serialize_with = "serialize_time")]
     end: NaiveTime,
     temperature: Temperature,
+    /// Temperature of the block that follows this one
+    next: Temperature,
+    /// `None` leaves the brightness as it is
+    brightness: Option<Brightness>,
+    next_brightness: Option<Brightness>,
+    /// Gamma correction of the preset, `None` leaves it as it is
+    gamma: Option<GammaCorrection>,
+    /// How long before `end` to start blending towards `next`
+    #[serde(serialize_with = "config::serialize_optional_duration")]
+    transition: Option<Duration>,
+    /// Trigger of the schedule entry that starts this block
+    trigger: ScheduleTrigger,
+    /// Preset the temperature was taken from
+    preset: Option<String>,
+    /// Only set for the block of a schedule with a single entry, it starts and ends at the
+    /// same time
+    #[serde(skip)]
+    whole_day: bool,
 }
 
 impl ScheduleBlock {
-    pub fn new(start: NaiveTime, end: NaiveTime, temperature: Temperature) -> Self {
-        Self {
-            start,
-            end,
-            temperature,
+    pub fn start(&self) -> NaiveTime {
+        self.start
+    }
+
+    pub fn end(&self) -> NaiveTime {
+        self.end
+    }
+
+    pub fn temperature(&self) -> Temperature {
+        self.temperature
+    }
+
+    pub fn gamma(&self) -> Option<GammaCorrection> {
+        self.gamma
+    }
+
+    pub fn next(&self) -> Temperature {
+        self.next
+    }
+
+    pub fn trigger(&self) -> &ScheduleTrigger {
+        &self.trigger
+    }
+
+    pub fn preset(&self) -> Option<&str> {
+        self.preset.as_deref()
+    }
+
+    /// Length of the block, wrapping around midnight
+    fn length(&self) -> chrono::Duration {
+        match self.whole_day {
+            true => chrono::Duration::days(1),
+            false => elapsed(self.start, self.end),
+        }
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        elapsed(self.start, time) < self.length()
+    }
+
+    /// How long the blend towards the next block takes, `None` if it switches instantly
+    pub fn transition_length(&self) -> Option<chrono::Duration> {
+        let transition = chrono::Duration::from_std(self.transition?)
+            .unwrap_or_else(|_| self.length())
+            .min(self.length());
+
+        match transition.is_zero() {
+            true => None,
+            false => Some(transition),
+        }
+    }
+
+    /// Time left until the end of the block
+    pub fn remaining(&self, time: NaiveTime) -> chrono::Duration {
+        self.length() - elapsed(self.start, time)
+    }
+
+    pub fn in_transition(&self, time: NaiveTime) -> bool {
+        self.transition_length()
+            .is_some_and(|transition| self.remaining(time) < transition)
+    }
+
+    /// Time until the temperature of this block changes next
+    pub fn next_change(&self, time: NaiveTime) -> chrono::Duration {
+        let remaining = self.remaining(time);
+        match self.transition_length() {
+            Some(transition) if remaining >= transition => remaining - transition,
+            Some(_) => TRANSITION_STEP.min(remaining),
+            None => remaining,
         }
     }
+
+    /// How far the transition towards the next block got, `None` outside of the transition
+    fn progress(&self, time: NaiveTime) -> Option<f64> {
+        let remaining = self.remaining(time);
+        match self.transition_length() {
+            Some(transition) if remaining < transition => {
+                Some(1.0 - remaining.num_seconds() as f64 / transition.num_seconds() as f64)
+            }
+            _ => None,
+        }
+    }
+
+    /// Temperature at the given time, blending towards the next block during the transition
+    pub fn temperature_at(&self, time: NaiveTime) -> Temperature {
+        let progress = match self.progress(time) {
+            Some(progress) => progress,
+            None => return self.temperature,
+        };
+
+        let (from, to) = (self.temperature.as_f64(), self.next.as_f64());
+        Temperature::new(from + (to - from) * progress)
+    }
+
+    /// Brightness at the given time, blending only when both blocks set one
+    pub fn brightness_at(&self, time: NaiveTime) -> Option<Brightness> {
+        match (self.brightness, self.next_brightness, self.progress(time)) {
+            (Some(from), Some(to), Some(progress)) => {
+                let (from, to) = (from.as_f64(), to.as_f64());
+                Brightness::try_from(from + (to - from) * progress).ok()
+            }
+            (brightness, _, _) => brightness,
+        }
+    }
+}
+
+fn serialize_time<S>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_str(&time.format("%H:%M"))
+}
+
+/// How often the temperature is updated while blending between two blocks
+const TRANSITION_STEP: chrono::Duration = chrono::Duration::seconds(10);
+/// Wait before trying again when the backend failed, e.g. while the X server restarts
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Time between `from` and `to`, wrapping around midnight
+fn elapsed(from: NaiveTime, to: NaiveTime) -> chrono::Duration {
+    match to.signed_duration_since(from) {
+        duration if duration < chrono::Duration::zero() => duration + chrono::Duration::days(1),
+        duration => duration,
+    }
 }
 
 pub fn parse_schedule(config: &Configuration) -> Vec<ScheduleBlock> {
-    let mut mapped: Vec<(NaiveTime, Temperature)> = config
+    let mut mapped: Vec<(NaiveTime, &Schedule)> = config
         .schedule
         .iter()
         .filter_map(|s| match s.get_time(&config.location) {
-            Ok(time) => {
-                let temperature = s.get_temperature(&config.presets);
-                Some((time, temperature))
-            }
-            Err(_) => {
-                eprintln!("Using time based schedule entry but no location was provided!");
+            Ok(time) => Some((time, s)),
+            Err(err) => {
+                eprintln!("Unable to resolve schedule entry `{}`: {err}", s.get_trigger());
                 None
             }
         })
         .collect();
 
     mapped.sort_by_key(|&(time, _)| time);
+    // Entries sharing a trigger time would start blocks of no length, the last one wins
+    let mapped: Vec<(NaiveTime, &Schedule)> = mapped
+        .iter()
+        .enumerate()
+        .filter(|&(i, &(time, _))| mapped.get(i + 1).is_none_or(|&(next, _)| next != time))
+        .map(|(_, &entry)| entry)
+        .collect();
 
     mapped
         .iter()
         .enumerate()
-        .map(|(i, &(start, temperature))| {
-            let end = mapped.get(i + 1).map_or(mapped[0].0, |&(end, _)| end);
-            ScheduleBlock::new(start, end, temperature)
+        .map(|(i, &(start, schedule))| {
+            let (end, next) = *mapped.get(i + 1).unwrap_or(&mapped[0]);
+            let mut block = ScheduleBlock {
+                start,
+                end,
+                temperature: schedule.get_temperature(&config.presets),
+                next: next.get_temperature(&config.presets),
+                brightness: schedule.get_brightness(&config.presets),
+                next_brightness: next.get_brightness(&config.presets),
+                gamma: schedule.get_gamma(&config.presets),
+                transition: next.get_transition(),
+                trigger: schedule.get_trigger().clone(),
+                preset: schedule.get_preset().map(String::from),
+                whole_day: mapped.len() == 1,
+            };
+            if config.interpolate {
+                // Blend over the whole block so every trigger is reached exactly on time
+                block.transition = block.length().to_std().ok();
+            }
+            block
         })
         .collect()
 }
 
-pub fn get_current_schedule(schedule: Vec<ScheduleBlock>) -> Option<ScheduleBlock> {
-    let now = chrono::Local::now().time();
+/// Finds the block active at the given time along with its (possibly interpolated) temperature
+pub fn get_current_schedule(
+    schedule: &[ScheduleBlock],
+    time: NaiveTime,
+) -> Option<(ScheduleBlock, Temperature)> {
+    schedule
+        .iter()
+        .find(|block| block.contains(time))
+        .map(|block| (block.clone(), block.temperature_at(time)))
+}
 
-    schedule.into_iter().find(|block| {
-        if block.end < block.start {
-            // Schedule spans midnight
-            now >= block.start || now < block.end
-        } else {
-            // Regular schedule
-            now >= block.start && now <= block.end
-        }
-    })
+pub enum DaemonEvent {
+    Stop,
+    Reload,
+    /// Outputs that were added or reconfigured, by id
+    OutputsChanged(Vec<u32>),
+    /// The system clock jumped, e.g. after a suspend or when NTP stepped it
+    ClockChanged,
+    Request(Request, Sender<Response>),
 }
 
-fn spawn_signal_handler() -> Result<()> {
-    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+fn spawn_signal_handler(sender: Sender<DaemonEvent>) -> Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
 
     thread::spawn(move || {
         for sig in signals.forever() {
-            println!("Received signal {:?}", sig);
+            log::info!("Received signal {:?}", sig);
+            let event = match sig {
+                SIGHUP => DaemonEvent::Reload,
+                _ => DaemonEvent::Stop,
+            };
+            if sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Reloads the configuration whenever the file changes. The directory is watched instead of
+/// the file itself because most editors replace the file when saving.
+fn spawn_config_watcher(path: &Path, sender: Sender<DaemonEvent>) -> Result<()> {
+    let (dir, file_name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(file_name)) => (dir.to_path_buf(), file_name.to_os_string()),
+        _ => anyhow::bail!("Invalid configuration path: {}", path.display()),
+    };
+
+    let mut inotify = Inotify::init()?;
+    inotify.watches().add(
+        &dir,
+        WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE,
+    )?;
+
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            let events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events,
+                Err(err) => {
+                    log::error!("Stopped watching configuration: {err}");
+                    break;
+                }
+            };
+
+            if events.into_iter().any(|event| event.name == Some(&file_name)) {
+                log::info!("Configuration file changed");
+                if sender.send(DaemonEvent::Reload).is_err() {
+                    break;
+                }
+            }
         }
     });
 
     Ok(())
 }
 
-fn start_event_loop(config: &Configuration, backend: &Backend) -> Result<()> {
-    // wait till the next full minute so we get a nice round number
-    let now = chrono::Local::now();
-    let next_minute = now.with_minute(now.minute() + 1).unwrap().remove_seconds();
+/// Sends `DaemonEvent::ClockChanged` whenever the system clock is set. Sleeps are measured on
+/// the monotonic clock, which doesn't move while suspended and ignores changes of the time.
+fn spawn_clock_watcher(sender: Sender<DaemonEvent>) -> Result<()> {
+    // SAFETY: timerfd_create takes no pointers, the descriptor is owned from here on
+    let timer = match unsafe { libc::timerfd_create(libc::CLOCK_REALTIME, libc::TFD_CLOEXEC) } {
+        -1 => return Err(std::io::Error::last_os_error().into()),
+        fd => unsafe { OwnedFd::from_raw_fd(fd) },
+    };
+    arm_clock_timer(&timer)?;
+
+    thread::spawn(move || loop {
+        let mut expirations = 0_u64;
+        // SAFETY: reading from a timerfd writes exactly 8 bytes
+        let read = unsafe {
+            libc::read(timer.as_raw_fd(), (&mut expirations as *mut u64).cast(), 8)
+        };
+        if read != -1 {
+            continue;
+        }
+
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ECANCELED) {
+            log::error!("Stopped watching for clock changes: {err}");
+            return;
+        }
+
+        log::info!("System clock changed");
+        if sender.send(DaemonEvent::ClockChanged).is_err() {
+            return;
+        }
+        if let Err(err) = arm_clock_timer(&timer) {
+            log::error!("Stopped watching for clock changes: {err}");
+            return;
+        }
+    });
+
+    Ok(())
+}
+
+/// Arms the timer at the end of time, it only exists to be cancelled when the clock is set
+fn arm_clock_timer(timer: &OwnedFd) -> Result<()> {
+    let never = libc::itimerspec {
+        it_interval: libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        },
+        it_value: libc::timespec {
+            tv_sec: libc::time_t::MAX,
+            tv_nsec: 0,
+        },
+    };
+    let flags = libc::TFD_TIMER_ABSTIME | libc::TFD_TIMER_CANCEL_ON_SET;
+
+    // SAFETY: `never` outlives the call and the old value isn't asked for
+    match unsafe { libc::timerfd_settime(timer.as_raw_fd(), flags, &never, std::ptr::null_mut()) } {
+        -1 => Err(std::io::Error::last_os_error().into()),
+        _ => Ok(()),
+    }
+}
+
+/// Time from `now` until the wall clock shows `wake`. Sleeps run on the monotonic clock, so a
+/// change to or from daylight saving time in between has to be accounted for here.
+fn time_until(wake: NaiveDateTime, now: DateTime<Local>) -> Duration {
+    let wake = match wake.and_local_timezone(Local).earliest() {
+        Some(wake) => wake,
+        // Skipped when the clocks move forward, wake up once they did
+        None => match (wake + chrono::Duration::hours(1)).and_local_timezone(Local).earliest() {
+            Some(wake) => wake,
+            None => return (wake - now.naive_local()).to_std().unwrap_or_default(),
+        },
+    };
+    (wake - now).to_std().unwrap_or_default()
+}
 
-    // TODO: Handle events that are scheduled before the next minute
-    let until_next_minute = next_minute.signed_duration_since(now).to_std()?;
-    log::debug!("Sleeping until next minute: {:?}", until_next_minute);
-    std::thread::sleep(until_next_minute);
+/// Waits for the given duration, or until an event arrives when there is none.
+/// Returns the event that interrupted the wait if any.
+fn wait_for_event(
+    receiver: &Receiver<DaemonEvent>,
+    timeout: Option<Duration>,
+) -> Option<DaemonEvent> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Some(receiver.recv().unwrap_or(DaemonEvent::Stop)),
+    };
 
+    match receiver.recv_timeout(timeout) {
+        Ok(event) => Some(event),
+        Err(RecvTimeoutError::Disconnected) => Some(DaemonEvent::Stop),
+        Err(RecvTimeoutError::Timeout) => None,
+    }
+}
+
+/// Waits between two steps of a fade. Requests that only read the status are answered right
+/// away, any other event cancels the fade and is left in `pending`.
+fn wait_during_fade(
+    receiver: &Receiver<DaemonEvent>,
+    pending: &mut Option<DaemonEvent>,
+    subscribers: &mut Vec<Sender<Response>>,
+    status: &dyn Fn() -> Status,
+    duration: Duration,
+) -> bool {
+    let deadline = Instant::now() + duration;
     loop {
-        let now = chrono::Local::now().remove_seconds().time();
-        let mode: Mode = match state::read() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match wait_for_event(receiver, Some(remaining)) {
+            None => return true,
+            Some(DaemonEvent::Request(Request::GetStatus, response)) => {
+                let _ = response.send(Response::Status(status()));
+            }
+            Some(DaemonEvent::Request(Request::Subscribe, response)) => {
+                let _ = response.send(Response::Status(status()));
+                subscribers.push(response);
+            }
+            Some(event) => {
+                *pending = Some(event);
+                return false;
+            }
+        }
+    }
+}
+
+fn read_status(
+    backend_name: &str,
+    config: &Configuration,
+    displays: &[String],
+    offset: Option<(NaiveTime, f64)>,
+) -> Status {
+    Status {
+        pid: std::process::id(),
+        backend: backend_name.to_string(),
+        mode: state::read(displays).unwrap_or(config.mode.clone()),
+        temperature: state::read(displays),
+        offset: offset.map_or(0.0, |(_, offset)| offset),
+    }
+}
+
+/// Requests that replace whatever an interrupted request was fading to
+fn supersedes(request: &Request) -> bool {
+    !matches!(
+        request,
+        Request::GetStatus | Request::Subscribe | Request::SetCorrection { .. } | Request::Reload
+    )
+}
+
+/// Outcome of a request, see `EventLoop::respond`
+enum Handled {
+    Done(Response),
+    /// A fade was interrupted by another event, the request is sent again once that one was
+    /// handled. It's rewritten where sending the original again would apply it twice.
+    Interrupted(Request),
+}
+
+struct EventLoop<'a> {
+    config: Configuration,
+    options: BackendOptions,
+    backend: &'a mut dyn GammaBackend,
+    backend_name: String,
+    receiver: Receiver<DaemonEvent>,
+    /// Event that interrupted a wait or fade and still has to be handled
+    pending: Option<DaemonEvent>,
+    /// Request whose fade was interrupted by `pending`, it's answered once it completes
+    interrupted: Option<(Request, Sender<Response>)>,
+    /// Target of an interrupted toggle, the state it started from is gone once it's resumed
+    toggling: Option<ToggleTarget>,
+    schedule: Vec<ScheduleBlock>,
+    /// Day the schedule was parsed for, sunrise and sunset move a little every day
+    schedule_date: Option<NaiveDate>,
+    /// Last temperature applied by the schedule, nothing is done until it changes
+    applied: Option<Temperature>,
+    /// Last brightness applied by the schedule
+    applied_brightness: Option<Brightness>,
+    /// Last gamma correction applied by the schedule
+    applied_gamma: Option<GammaCorrection>,
+    /// Relative adjustment made in dynamic mode and the start of the block it was made in,
+    /// it's dropped once the schedule moves on to the next block
+    offset: Option<(NaiveTime, f64)>,
+    /// Clients waiting for status changes, see `Request::Subscribe`
+    subscribers: Vec<Sender<Response>>,
+    /// Status last sent to the subscribers
+    notified: Option<Status>,
+}
+
+impl EventLoop<'_> {
+    fn run(&mut self) -> Result<()> {
+        loop {
+            if let Some(DaemonEvent::Request(request, _)) = &self.pending {
+                if supersedes(request) {
+                    if let Some((_, response)) = self.interrupted.take() {
+                        let message = "Interrupted by another request".to_string();
+                        let _ = response.send(Response::Error { message });
+                    }
+                    self.toggling = None;
+                }
+            }
+
+            match self.pending.take() {
+                Some(DaemonEvent::Stop) => return Ok(()),
+                Some(DaemonEvent::Request(Request::Stop, response)) => {
+                    let _ = response.send(Response::Ok);
+                    return Ok(());
+                }
+                Some(DaemonEvent::Reload) => {
+                    if let Err(err) = self.reload() {
+                        log::error!("Keeping previous configuration, {err}");
+                    }
+                }
+                Some(DaemonEvent::ClockChanged) => {
+                    // Sunrise and sunset might belong to another day now
+                    self.schedule_date = None;
+                }
+                Some(DaemonEvent::OutputsChanged(outputs)) => {
+                    if let Err(err) = self.reapply(&outputs) {
+                        log::error!("Unable to apply temperature to changed outputs: {err}");
+                    }
+                }
+                Some(DaemonEvent::Request(Request::Subscribe, response)) => {
+                    // Subscribers that went away are dropped on the next notification
+                    let _ = response.send(Response::Status(self.status()));
+                    self.subscribers.push(response);
+                }
+                Some(DaemonEvent::Request(request, response)) => self.respond(request, response),
+                None => {}
+            }
+
+            // A fade was interrupted by another event, handle that one first
+            if self.pending.is_some() {
+                continue;
+            }
+
+            // Pick up the fade of a request where the event that interrupted it left off
+            if let Some((request, response)) = self.interrupted.take() {
+                self.respond(request, response);
+                continue;
+            }
+
+            let timeout = match self.apply_schedule() {
+                Ok(timeout) => timeout,
+                Err(err) => {
+                    log::error!("Unable to apply schedule, retrying in {RETRY_INTERVAL:?}: {err}");
+                    Some(RETRY_INTERVAL)
+                }
+            };
+            self.notify_subscribers();
+
+            if self.pending.is_none() {
+                match timeout {
+                    Some(timeout) => log::debug!("Sleeping for {:?}", timeout),
+                    None => log::debug!("Sleeping until the next event"),
+                }
+                self.pending = wait_for_event(&self.receiver, timeout);
+            }
+        }
+    }
+
+    /// Applies the current temperature if it changed, returns how long to sleep until the next
+    /// change. `None` when nothing changes until an event arrives.
+    fn apply_schedule(&mut self) -> Result<Option<Duration>> {
+        let now = chrono::Local::now();
+        let mode: Mode = match state::read(&self.options.displays) {
             Some(mode) => mode,
-            None => config.mode.clone(),
+            None => self.config.mode.clone(),
         };
 
+        if self.schedule_date != Some(now.date_naive()) {
+            self.schedule = parse_schedule(&self.config);
+            self.schedule_date = Some(now.date_naive());
+        }
+
         log::debug!("Checking event at {:?}", now);
 
-        match mode {
-            Mode::Dynamic => {
-                let schedule = parse_schedule(config); // TODO: optimize
-                if let Some(block) = get_current_schedule(schedule) {
-                    log::info!("matched schedule: {:?}", block);
-                    backend.set_temperature(block.temperature)?;
-                    log::info!("set temperature to {}", block.temperature);
+        if mode == Mode::Static {
+            log::debug!("Mode is set to static, waiting for a mode change");
+            return Ok(None);
+        }
+
+        if let Some(temporary) = state::read::<Override>(&self.options.displays) {
+            match temporary.remaining() {
+                Some(remaining) => return self.apply_override(temporary.temperature, remaining),
+                None => {
+                    log::info!("Override expired, resuming schedule");
+                    state::clear::<Override>(&self.options.displays)?;
+                    self.applied = None;
+                }
+            }
+        }
+
+        let (block, temperature) = match get_current_schedule(&self.schedule, now.time()) {
+            Some(current) => current,
+            None => return Ok(None),
+        };
+
+        if self.offset.is_some_and(|(start, _)| start != block.start()) {
+            log::info!("Schedule moved on, dropping temperature offset");
+            self.offset = None;
+        }
+        let temperature = match self.offset {
+            Some((_, offset)) => self.config.adjust.clamp(temperature.as_f64() + offset),
+            None => temperature,
+        };
+
+        let changed = self
+            .applied
+            .is_none_or(|applied| applied.as_f64().round() != temperature.as_f64().round());
+
+        // Blocks without a brightness leave whatever was set last alone
+        let brightness = block.brightness_at(now.time());
+        let brightness_changed = brightness.is_some_and(|brightness| {
+            self.applied_brightness
+                .is_none_or(|applied| (applied.as_f64() - brightness.as_f64()).abs() >= 0.01)
+        });
+        if let (Some(brightness), true) = (brightness, brightness_changed) {
+            state::write(&self.options.displays, brightness)?;
+            self.applied_brightness = Some(brightness);
+        }
+
+        // Gamma correction switches at the start of the block instead of blending
+        let gamma = block.gamma().filter(|gamma| self.applied_gamma != Some(*gamma));
+        if let Some(gamma) = gamma {
+            state::write(&self.options.displays, gamma)?;
+            self.applied_gamma = Some(gamma);
+        }
+
+        if changed || brightness_changed || gamma.is_some() {
+            log::info!("matched schedule: {:?}", block);
+            // Small steps of a transition are applied directly instead of fading each of them
+            let fade = match block.in_transition(now.time()) {
+                true => Fade::default(),
+                false => self.config.fade.clone(),
+            };
+            if self.fade(temperature, None, &fade)? {
+                log::info!("set temperature to {}", temperature);
+                self.applied = Some(temperature);
+            }
+        }
+
+        let wake = now.naive_local() + block.next_change(now.time());
+        Ok(Some(time_until(wake, now)))
+    }
+
+    /// Keeps the temperature of an override applied until it expires
+    fn apply_override(
+        &mut self,
+        temperature: Temperature,
+        remaining: chrono::Duration,
+    ) -> Result<Option<Duration>> {
+        if self.applied.is_none_or(|applied| applied.as_f64() != temperature.as_f64()) {
+            let fade = self.config.fade.clone();
+            if self.fade(temperature, None, &fade)? {
+                self.applied = Some(temperature);
+            }
+        }
+
+        Ok(Some(remaining.to_std().unwrap_or_default()))
+    }
+
+    fn status(&self) -> Status {
+        read_status(
+            &self.backend_name,
+            &self.config,
+            &self.options.displays,
+            self.offset,
+        )
+    }
+
+    /// Fades to `temperature` while answering status requests, returns false when another
+    /// event interrupted it. That event is left in `pending`.
+    fn fade(&mut self, temperature: Temperature, only: Option<&str>, fade: &Fade) -> Result<bool> {
+        let offset = self.offset;
+        let displays = &self.options.displays;
+        self.backend.fade_temperature(
+            temperature,
+            &self.config,
+            displays,
+            only,
+            fade,
+            &mut |duration| {
+                wait_during_fade(
+                    &self.receiver,
+                    &mut self.pending,
+                    &mut self.subscribers,
+                    &|| read_status(&self.backend_name, &self.config, displays, offset),
+                    duration,
+                )
+            },
+        )
+    }
+
+    /// Answers a request, one whose fade was interrupted is answered once it's resumed and done
+    fn respond(&mut self, request: Request, response: Sender<Response>) {
+        let response_value = match self.handle_request(request) {
+            Ok(Handled::Done(value)) => value,
+            Ok(Handled::Interrupted(request)) => {
+                self.interrupted = Some((request, response));
+                return;
+            }
+            Err(err) => Response::Error {
+                message: err.to_string(),
+            },
+        };
+        let _ = response.send(response_value);
+    }
+
+    /// Sends the status to every subscriber if it changed, dropping the ones that went away
+    fn notify_subscribers(&mut self) {
+        let status = self.status();
+        if self.notified.as_ref() == Some(&status) {
+            return;
+        }
+
+        self.subscribers
+            .retain(|subscriber| subscriber.send(Response::Status(status.clone())).is_ok());
+        self.notified = Some(status);
+    }
+
+    fn handle_request(&mut self, request: Request) -> Result<Handled> {
+        match request {
+            Request::GetStatus => Ok(Handled::Done(Response::Status(self.status()))),
+            Request::SetTemperature {
+                temperature,
+                output,
+                fade,
+                brightness,
+                gamma,
+            } => {
+                backends::write_correction(&self.options.displays, brightness, gamma)?;
+                self.set_temperature(temperature, output, fade)
+            }
+            Request::ApplyPreset {
+                preset,
+                output,
+                fade,
+                brightness,
+                gamma,
+            } => match self.config.presets.iter().find(|p| p.name == preset) {
+                Some(preset) => {
+                    backends::write_correction(
+                        &self.options.displays,
+                        brightness.or(preset.brightness),
+                        gamma.or(preset.gamma),
+                    )?;
+                    self.set_temperature(preset.temperature, output, fade)
                 }
+                None => anyhow::bail!("No such preset: {preset}"),
+            },
+            Request::SetCorrection { brightness, gamma } => {
+                backends::write_correction(&self.options.displays, brightness, gamma)?;
+                let displays = &self.options.displays;
+                let temperature =
+                    state::read(displays).unwrap_or(Temperature::new(Temperature::NEUTRAL));
+                self.backend
+                    .apply_temperature(temperature, &self.config, displays, None)?;
+                Ok(Handled::Done(Response::Ok))
+            }
+            Request::AdjustTemperature { delta, fade } => self.adjust_temperature(delta, fade),
+            Request::SetOverride {
+                temperature,
+                until,
+                fade,
+            } => self.set_override(temperature, until, fade),
+            Request::Toggle => self.toggle(),
+            Request::Reset => {
+                reset_gamma(self.backend, &self.options.displays)?;
+                self.applied = None;
+                self.applied_brightness = None;
+                self.applied_gamma = None;
+                self.offset = None;
+                Ok(Handled::Done(Response::Ok))
+            }
+            Request::SetMode { mode } => {
+                state::clear::<Override>(&self.options.displays)?;
+                state::clear::<Toggled>(&self.options.displays)?;
+                state::write(&self.options.displays, mode)?;
+                self.applied = None;
+                self.applied_brightness = None;
+                self.applied_gamma = None;
+                self.offset = None;
+                Ok(Handled::Done(Response::Ok))
             }
-            Mode::Static => {
-                log::debug!("Mode is set to static, sleeping until next minute");
+            Request::Reload => {
+                self.reload()?;
+                Ok(Handled::Done(Response::Ok))
             }
+            Request::Stop | Request::Subscribe => {
+                unreachable!("{request:?} requests are handled by the event loop")
+            }
+        }
+    }
+
+    /// Replaces the configuration with the one on disk, the current one is kept if it's invalid
+    fn reload(&mut self) -> Result<()> {
+        let path = match &self.config.path {
+            Some(path) => path.clone(),
+            None => anyhow::bail!("Daemon was started without a configuration file"),
+        };
+
+        let config = Configuration::from_path(&path)?;
+        let connection = |config: &Configuration| {
+            (config.display.clone(), config.console.clone(), config.drm_card.clone())
+        };
+        if connection(&config) != connection(&self.config) {
+            log::warn!("Changes to display, console and drm_card apply once the daemon restarts");
+        }
+        self.config = config;
+        // Evaluate the new schedule right away
+        self.schedule_date = None;
+        self.applied = None;
+        self.applied_brightness = None;
+        self.applied_gamma = None;
+        self.offset = None;
+        log::info!("Reloaded configuration from {}", path.display());
+        Ok(())
+    }
+
+    /// Applies the current temperature to outputs that came up with a fresh ramp,
+    /// in static mode nothing else would ever touch them
+    fn reapply(&mut self, outputs: &[u32]) -> Result<()> {
+        let displays = &self.options.displays;
+        let temperature = state::read(displays).unwrap_or(Temperature::new(Temperature::NEUTRAL));
+        for output in self.backend.outputs()? {
+            if output.id.is_some_and(|id| outputs.contains(&id)) {
+                log::info!("Output {} changed, applying {temperature}K", output.name);
+                self.backend.apply_temperature(
+                    temperature,
+                    &self.config,
+                    displays,
+                    Some(&output.name),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switches to a neutral temperature, or back to whatever was active before
+    fn toggle(&mut self) -> Result<Handled> {
+        let target = match self.toggling.take() {
+            Some(target) => target,
+            None => ToggleTarget::current(&self.config, &self.options.displays),
+        };
+        let fade = self.config.fade.clone();
+        let displays = self.options.displays.clone();
+        let toggled = toggle_neutral(target.clone(), &displays, &mut |temperature| {
+            self.fade(temperature, None, &fade)
+        })?;
+        if !toggled {
+            self.toggling = Some(target);
+            return Ok(Handled::Interrupted(Request::Toggle));
+        }
+        // In dynamic mode the schedule picks it up from here
+        self.applied = None;
+        self.offset = None;
+
+        Ok(Handled::Done(Response::Ok))
+    }
+
+    /// Applies the temperature in place of the schedule until the unix timestamp `until`
+    fn set_override(
+        &mut self,
+        temperature: Temperature,
+        until: i64,
+        fade: Option<u64>,
+    ) -> Result<Handled> {
+        let until = match DateTime::from_timestamp(until, 0) {
+            Some(until) => until.with_timezone(&Local),
+            None => anyhow::bail!("Invalid expiry time: {until}"),
+        };
+
+        state::write(&self.options.displays, Override { temperature, until })?;
+        state::write(&self.options.displays, Mode::Dynamic)?;
+        state::clear::<Toggled>(&self.options.displays)?;
+        self.applied = None;
+        self.offset = None;
+        log::info!("Overriding schedule with {temperature}K until {until}");
+
+        // Applying it right away lets the request control the fade
+        let duration = Duration::from_millis(fade.unwrap_or_default());
+        if !self.fade(temperature, None, &Fade::new(duration, self.config.fade.easing))? {
+            return Ok(Handled::Interrupted(Request::SetOverride {
+                temperature,
+                until: until.timestamp(),
+                fade,
+            }));
+        }
+        self.applied = Some(temperature);
+
+        Ok(Handled::Done(Response::Ok))
+    }
+
+    /// Moves the temperature by `delta`, in dynamic mode the change is kept as an offset on top
+    /// of the schedule instead of switching to static mode.
+    fn adjust_temperature(&mut self, delta: f64, fade: Option<u64>) -> Result<Handled> {
+        let displays = &self.options.displays;
+        let mode: Mode = state::read(displays).unwrap_or(self.config.mode.clone());
+        if mode == Mode::Static {
+            let current =
+                state::read::<Temperature>(displays).map_or(Temperature::NEUTRAL, |t| t.as_f64());
+            let temperature = self.config.adjust.clamp(current + delta);
+            return self.set_temperature(temperature, None, fade);
+        }
+
+        let now = chrono::Local::now().time();
+        let (block, scheduled) = match get_current_schedule(&self.schedule, now) {
+            Some(current) => current,
+            None => anyhow::bail!("No schedule entry is active"),
+        };
+        // Relative to where the schedule is heading, not to a step of a fade that is underway
+        let offset = match self.offset {
+            Some((start, offset)) if start == block.start() => offset,
+            _ => 0.0,
+        };
+        let temperature = self.config.adjust.clamp(scheduled.as_f64() + offset + delta);
+        // The adjustment replaces an override and moves along with the schedule instead
+        state::clear::<Override>(&self.options.displays)?;
+        state::clear::<Toggled>(&self.options.displays)?;
+        self.offset = Some((block.start(), temperature.as_f64() - scheduled.as_f64()));
+
+        let request_fade = fade;
+        let fade = Fade::new(
+            Duration::from_millis(fade.unwrap_or_default()),
+            self.config.fade.easing,
+        );
+        if !self.fade(temperature, None, &fade)? {
+            // The offset is in place already, only the fade is left to do
+            return Ok(Handled::Interrupted(Request::AdjustTemperature {
+                delta: 0.0,
+                fade: request_fade,
+            }));
+        }
+        // Let the schedule pick it up from here, the offset moves along with transitions
+        self.applied = Some(temperature);
+
+        Ok(Handled::Done(Response::Ok))
+    }
+
+    /// Switches to static mode and fades to `temperature`. When interrupted the same
+    /// temperature is set again, brightness and gamma correction were written already.
+    fn set_temperature(
+        &mut self,
+        temperature: Temperature,
+        output: Option<String>,
+        fade: Option<u64>,
+    ) -> Result<Handled> {
+        state::write(&self.options.displays, Mode::Static)?;
+        state::clear::<Override>(&self.options.displays)?;
+        state::clear::<Toggled>(&self.options.displays)?;
+        self.applied = None;
+        self.offset = None;
+
+        let duration = Duration::from_millis(fade.unwrap_or_default());
+        let faded = self.fade(
+            temperature,
+            output.as_deref(),
+            &Fade::new(duration, self.config.fade.easing),
+        )?;
+        match faded {
+            true => Ok(Handled::Done(Response::Ok)),
+            false => Ok(Handled::Interrupted(Request::SetTemperature {
+                temperature,
+                output,
+                fade,
+                brightness: None,
+                gamma: None,
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(content: &str) -> Vec<ScheduleBlock> {
+        let content = format!("presets = []\n{content}");
+        let config: Configuration = toml::from_str(&content).expect("valid configuration");
+        parse_schedule(&config)
+    }
+
+    fn time(value: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(value, "%H:%M").expect("valid time")
+    }
+
+    fn current(schedule: &[ScheduleBlock], at: &str) -> (ScheduleBlock, Temperature) {
+        get_current_schedule(schedule, time(at)).expect("a block to be active")
+    }
+
+    const DAY_AND_NIGHT: &str = r#"
+        [[schedule]]
+        trigger = "07:00"
+        temperature = 6500
+
+        [[schedule]]
+        trigger = "21:00"
+        temperature = 4000
+        transition = "1h"
+    "#;
+
+    #[test]
+    fn contains_wraps_around_midnight() {
+        let schedule = schedule(DAY_AND_NIGHT);
+        let night = schedule.iter().find(|b| b.start() == time("21:00")).unwrap();
+
+        assert!(night.contains(time("21:00")));
+        assert!(night.contains(time("23:59")));
+        assert!(night.contains(time("00:00")));
+        assert!(night.contains(time("06:59")));
+        assert!(!night.contains(time("07:00")));
+        assert!(!night.contains(time("12:00")));
+    }
+
+    #[test]
+    fn single_entry_spans_the_whole_day() {
+        let schedule = schedule("[[schedule]]\ntrigger = \"04:00\"\ntemperature = 5000");
+
+        assert_eq!(schedule.len(), 1);
+        for at in ["04:00", "12:00", "03:59"] {
+            assert!(schedule[0].contains(time(at)), "{at}");
         }
+        assert_eq!(
+            schedule[0].next_change(time("04:00")),
+            chrono::Duration::days(1)
+        );
+    }
+
+    #[test]
+    fn duplicate_times_keep_the_last_entry() {
+        let schedule = schedule(
+            r#"
+            [[schedule]]
+            trigger = "04:00"
+            temperature = 6500
+
+            [[schedule]]
+            trigger = "04:00"
+            temperature = 5000
+
+            [[schedule]]
+            trigger = "05:00"
+            temperature = 4000
+            "#,
+        );
+
+        assert_eq!(schedule.len(), 2);
+        let (block, temperature) = current(&schedule, "06:59");
+        assert_eq!((block.start(), block.end()), (time("05:00"), time("04:00")));
+        assert_eq!(temperature, Temperature::new(4000.0));
+        assert_eq!(block.next(), Temperature::new(5000.0));
+
+        let (block, temperature) = current(&schedule, "04:30");
+        assert_eq!((block.start(), block.end()), (time("04:00"), time("05:00")));
+        assert_eq!(temperature, Temperature::new(5000.0));
+    }
+
+    #[test]
+    fn duplicate_times_only() {
+        let schedule = schedule(
+            r#"
+            [[schedule]]
+            trigger = "04:00"
+            temperature = 6500
+
+            [[schedule]]
+            trigger = "04:00"
+            temperature = 5000
+            "#,
+        );
+
+        assert_eq!(schedule.len(), 1);
+        assert_eq!(current(&schedule, "03:00").1, Temperature::new(5000.0));
+    }
+
+    #[test]
+    fn temperature_blends_during_transition() {
+        let schedule = schedule(DAY_AND_NIGHT);
+
+        assert_eq!(current(&schedule, "12:00").1, Temperature::new(6500.0));
+        assert_eq!(current(&schedule, "20:00").1, Temperature::new(6500.0));
+        assert_eq!(current(&schedule, "20:30").1, Temperature::new(5250.0));
+        assert_eq!(current(&schedule, "21:00").1, Temperature::new(4000.0));
+        // The night block has no transition of its own
+        assert_eq!(current(&schedule, "06:59").1, Temperature::new(4000.0));
+    }
+
+    #[test]
+    fn interpolation_blends_across_midnight() {
+        let schedule = schedule(
+            r#"
+            interpolate = true
+
+            [[schedule]]
+            trigger = "22:00"
+            temperature = 6000
+
+            [[schedule]]
+            trigger = "02:00"
+            temperature = 4000
+            "#,
+        );
+
+        assert_eq!(current(&schedule, "22:00").1, Temperature::new(6000.0));
+        assert_eq!(current(&schedule, "00:00").1, Temperature::new(5000.0));
+        assert_eq!(current(&schedule, "02:00").1, Temperature::new(4000.0));
+    }
+
+    #[test]
+    fn next_change_waits_for_the_transition() {
+        let schedule = schedule(DAY_AND_NIGHT);
+        let minutes = chrono::Duration::minutes;
 
-        std::thread::sleep(std::time::Duration::from_secs(60));
+        // Until the transition towards the night block starts at 20:00
+        assert_eq!(current(&schedule, "12:00").0.next_change(time("12:00")), minutes(8 * 60));
+        // Small steps while blending
+        assert_eq!(current(&schedule, "20:30").0.next_change(time("20:30")), TRANSITION_STEP);
+        // Until the day block starts, across midnight
+        assert_eq!(current(&schedule, "23:00").0.next_change(time("23:00")), minutes(8 * 60));
     }
 }
@@ -0,0 +1,274 @@
+use crate::{
+    backends::{Brightness, GammaCorrection, Temperature},
+    config::Mode,
+    daemon::DaemonEvent,
+    utils::{self},
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    str::FromStr,
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+/// Requests sent to the daemon, one JSON object per line.
+/// e.g. `{"command": "set-temperature", "temperature": 4500, "fade": 2000}`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    GetStatus,
+    SetTemperature {
+        temperature: Temperature,
+        output: Option<String>,
+        /// Fade duration in milliseconds
+        fade: Option<u64>,
+        #[serde(default)]
+        brightness: Option<Brightness>,
+        #[serde(default)]
+        gamma: Option<GammaCorrection>,
+    },
+    /// Changes the brightness or gamma correction while keeping the current temperature
+    SetCorrection {
+        #[serde(default)]
+        brightness: Option<Brightness>,
+        #[serde(default)]
+        gamma: Option<GammaCorrection>,
+    },
+    /// Changes the temperature by `delta` Kelvin, on top of the schedule in dynamic mode
+    AdjustTemperature {
+        delta: f64,
+        /// Fade duration in milliseconds
+        fade: Option<u64>,
+    },
+    /// Replaces the schedule with `temperature` until the unix timestamp `until`
+    SetOverride {
+        temperature: Temperature,
+        until: i64,
+        /// Fade duration in milliseconds
+        fade: Option<u64>,
+    },
+    SetMode {
+        mode: Mode,
+    },
+    ApplyPreset {
+        preset: String,
+        output: Option<String>,
+        /// Fade duration in milliseconds
+        fade: Option<u64>,
+        /// Takes precedence over the brightness of the preset
+        #[serde(default)]
+        brightness: Option<Brightness>,
+        /// Takes precedence over the gamma of the preset
+        #[serde(default)]
+        gamma: Option<GammaCorrection>,
+    },
+    /// Switches between a neutral temperature and whatever was active before
+    Toggle,
+    /// Sets a linear gamma ramp on every output and switches to static mode
+    Reset,
+    Reload,
+    Stop,
+    /// Keeps the connection open and sends the status every time it changes
+    Subscribe,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum Response {
+    Ok,
+    Status(Status),
+    Error { message: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Status {
+    pub pid: u32,
+    pub backend: String,
+    pub mode: Mode,
+    pub temperature: Option<Temperature>,
+    /// Offset added to the schedule by relative adjustments
+    #[serde(default)]
+    pub offset: f64,
+}
+
+/// One socket for every selected display, a daemon managing several displays listens on all of them
+pub fn socket_paths(displays: &[String]) -> Vec<PathBuf> {
+    let runtime_dir = match std::env::var("XDG_RUNTIME_DIR") {
+        Ok(path) => PathBuf::from_str(&path).expect("Valid path"),
+        Err(_) => utils::home_dir().join(".cache"),
+    };
+
+    match displays {
+        [] => vec![runtime_dir.join("bluegone.sock")],
+        displays => displays
+            .iter()
+            .map(|display| runtime_dir.join(format!("bluegone-{}.sock", display.replace('/', "_"))))
+            .collect(),
+    }
+}
+
+/// Connects to the daemon managing the first display in `displays` that has one
+fn connect(displays: &[String]) -> Option<UnixStream> {
+    socket_paths(displays)
+        .into_iter()
+        .find_map(|path| UnixStream::connect(path).ok())
+}
+
+fn write_line<T: Serialize>(stream: &UnixStream, value: &T) -> Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    (&*stream).write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Sends a request to the daemon, returns `None` if no daemon is listening.
+pub fn send(displays: &[String], request: &Request) -> Result<Option<Response>> {
+    let stream = match connect(displays) {
+        Some(stream) => stream,
+        None => return Ok(None),
+    };
+
+    write_line(&stream, request)?;
+
+    let mut line = String::new();
+    if BufReader::new(&stream).read_line(&mut line)? == 0 {
+        // The daemon might exit before it gets to answer a stop request
+        return match request {
+            Request::Stop => Ok(Some(Response::Ok)),
+            _ => anyhow::bail!("Daemon closed the connection"),
+        };
+    }
+
+    match serde_json::from_str(&line)? {
+        Response::Error { message } => anyhow::bail!("Daemon error: {message}"),
+        response => Ok(Some(response)),
+    }
+}
+
+/// Calls `on_status` with the status of the daemon every time it changes until the daemon exits,
+/// returns false if no daemon is listening.
+pub fn subscribe(
+    displays: &[String],
+    mut on_status: impl FnMut(Status) -> Result<()>,
+) -> Result<bool> {
+    let stream = match connect(displays) {
+        Some(stream) => stream,
+        None => return Ok(false),
+    };
+
+    write_line(&stream, &Request::Subscribe)?;
+
+    for line in BufReader::new(&stream).lines() {
+        match serde_json::from_str(&line?)? {
+            Response::Status(status) => on_status(status)?,
+            Response::Error { message } => anyhow::bail!("Daemon error: {message}"),
+            Response::Ok => {}
+        }
+    }
+
+    Ok(true)
+}
+
+/// Binds the sockets and forwards every request to the daemon along with a channel for the response.
+pub fn spawn_listener(displays: &[String], sender: Sender<DaemonEvent>) -> Result<()> {
+    for path in socket_paths(displays) {
+        // A lingering socket from a daemon that didn't exit cleanly
+        if UnixStream::connect(&path).is_err() && std::fs::metadata(&path).is_ok() {
+            std::fs::remove_file(&path)?;
+        }
+
+        spawn_accept_loop(UnixListener::bind(&path)?, sender.clone());
+    }
+
+    Ok(())
+}
+
+fn spawn_accept_loop(listener: UnixListener, sender: Sender<DaemonEvent>) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    log::warn!("Unable to accept connection: {err}");
+                    continue;
+                }
+            };
+
+            let sender = sender.clone();
+            thread::spawn(move || {
+                if let Err(err) = handle_connection(stream, sender) {
+                    log::warn!("Closing connection: {err}");
+                }
+            });
+        }
+    });
+}
+
+fn handle_connection(stream: UnixStream, sender: Sender<DaemonEvent>) -> Result<()> {
+    let reader = BufReader::new(&stream);
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => request,
+            Err(err) => {
+                let message = format!("Invalid request: {err}");
+                write_line(&stream, &Response::Error { message })?;
+                continue;
+            }
+        };
+
+        log::debug!("Received request: {:?}", request);
+        let subscribe = matches!(request, Request::Subscribe);
+        let (response_sender, response_receiver) = mpsc::channel();
+        sender.send(DaemonEvent::Request(request, response_sender))?;
+
+        if subscribe {
+            // The daemon holds on to the sender and keeps sending updates
+            for response in response_receiver {
+                write_line(&stream, &response)?;
+            }
+            return Ok(());
+        }
+
+        write_line(&stream, &response_receiver.recv()?)?;
+    }
+
+    Ok(())
+}
+
+pub fn remove_sockets(displays: &[String]) -> Result<()> {
+    for path in socket_paths(displays) {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn socket_names(displays: &[&str]) -> Vec<String> {
+        let displays: Vec<String> = displays.iter().map(|display| display.to_string()).collect();
+        socket_paths(&displays)
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn one_socket_per_display() {
+        assert_eq!(socket_names(&[]), ["bluegone.sock"]);
+        assert_eq!(
+            socket_names(&[":0", "/tmp/.X11-unix/X1"]),
+            ["bluegone-:0.sock", "bluegone-_tmp_.X11-unix_X1.sock"]
+        );
+    }
+}
@@ -1,14 +1,19 @@
 mod backends;
+mod blackbody;
 mod cli;
 mod config;
 mod daemon;
+mod ipc;
 mod state;
 mod utils;
 
 use std::path::PathBuf;
 use anyhow::Result;
-use backends::Backend;
-use clap::{builder::EnumValueParser, command, value_parser, Arg};
+use backends::{Backend, BackendOptions};
+use clap::{
+    builder::{EnumValueParser, PossibleValuesParser, TypedValueParser},
+    command, value_parser, Arg, ArgAction,
+};
 use config::Configuration;
 
 fn main() -> Result<()> {
@@ -30,27 +35,100 @@ fn main() -> Result<()> {
                 .required(false)
                 .long("backend")
                 .help("Backend to use")
-                .value_parser(EnumValueParser::<Backend>::new()),
+                .ignore_case(true)
+                .value_parser(
+                    PossibleValuesParser::new(Backend::names())
+                        .try_map(|name| Backend::try_from(name.as_str())),
+                ),
+        )
+        .arg(
+            Arg::new("display")
+                .long("display")
+                .global(true)
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .help("X displays to connect to instead of $DISPLAY, e.g. :0,:1"),
+        )
+        .arg(
+            Arg::new("console")
+                .long("console")
+                .global(true)
+                .help("Virtual console of the console backend instead of /dev/tty0, e.g. /dev/tty2")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("drm_card")
+                .long("drm-card")
+                .global(true)
+                .help("Card of the drm backend instead of the first one with CRTCs, e.g. /dev/dri/card1")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .global(true)
+                .help("Output format of commands that print information")
+                .value_parser(EnumValueParser::<cli::Format>::new())
+                .default_value("text"),
         )
         .subcommand(cli::init_info_subcommand())
         .subcommand(cli::init_daemon_subcommand())
         .subcommand(cli::init_list_subcommand())
         .subcommand(cli::init_set_subcommand())
+        .subcommand(cli::init_config_subcommand())
+        .subcommand(cli::init_status_subcommand())
+        .subcommand(cli::init_cycle_subcommand())
+        .subcommand(cli::init_pause_subcommand())
+        .subcommand(cli::init_toggle_subcommand())
+        .subcommand(cli::init_reset_subcommand())
+        .subcommand(cli::init_warmer_subcommand())
+        .subcommand(cli::init_cooler_subcommand())
         .get_matches();
 
+    if let Some(("config", sub_args)) = args.subcommand() {
+        return cli::handle_config_subcommand(sub_args, &args);
+    }
+
     let mut sys = sysinfo::System::new_all();
 
     let config = Configuration::get_config(&args)?;
+    let options = BackendOptions {
+        displays: match args.get_many::<String>("display") {
+            Some(displays) => displays.cloned().collect(),
+            None => config.display.clone(),
+        },
+        console: match args.get_one::<PathBuf>("console") {
+            Some(console) => Some(console.clone()),
+            None => config.console.clone(),
+        },
+        drm_card: match args.get_one::<PathBuf>("drm_card") {
+            Some(card) => Some(card.clone()),
+            None => config.drm_card.clone(),
+        },
+    };
     let backend = match args.get_one::<Backend>("backend") {
         Some(backend) => backend,
         None => &config.backend,
     };
 
     match args.subcommand() {
-        Some(("set", args)) => cli::handle_set_subcommand(args, backend, config),
-        Some(("info", args)) => cli::handle_info_subcommand(args, backend, config, &mut sys),
-        Some(("daemon", args)) => cli::handle_daemon_subcommand(args, backend, config, &mut sys),
-        Some(("list", args)) => cli::handle_list_subcommand(args, config),
+        Some(("set", args)) => cli::handle_set_subcommand(args, backend, &options, config),
+        Some(("info", args)) => {
+            cli::handle_info_subcommand(args, backend, &options, config, &mut sys)
+        }
+        Some(("daemon", args)) => {
+            cli::handle_daemon_subcommand(args, backend, &options, config, &mut sys)
+        }
+        Some(("list", args)) => cli::handle_list_subcommand(args, backend, &options, config),
+        Some(("status", args)) => cli::handle_status_subcommand(args, backend, &options, config),
+        Some(("cycle", args)) => cli::handle_cycle_subcommand(args, backend, &options, config),
+        Some(("pause", args)) => cli::handle_pause_subcommand(args, backend, &options, config),
+        Some(("toggle", _)) => cli::handle_toggle_subcommand(backend, &options, config),
+        Some(("reset", _)) => cli::handle_reset_subcommand(backend, &options),
+        Some(("warmer", args)) => {
+            cli::handle_step_subcommand(args, backend, &options, config, -1.0)
+        }
+        Some(("cooler", args)) => cli::handle_step_subcommand(args, backend, &options, config, 1.0),
         None | Some((_, _)) => anyhow::bail!("No subcommand provided."),
     }
 }
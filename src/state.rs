@@ -1,45 +1,66 @@
 use anyhow::Result;
 use bluegone::StateFileName;
-use crate::utils::get_data_path;
+use crate::utils::get_state_paths;
 use std::{fmt::Display, path::PathBuf};
 
-pub fn write<T>(value: T) -> Result<()>
+/// Writes the state of every display in `displays`
+pub fn write<T>(displays: &[String], value: T) -> Result<()>
 where
     T: Display + StateFileName,
 {
-    let path = get_data_path().join(T::name());
-    std::fs::create_dir_all(get_data_path())?;
-    std::fs::write(path, value.to_string())?;
+    let content = value.to_string();
+    for dir in get_state_paths(displays) {
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(T::name()), &content)?;
+    }
     Ok(())
 }
 
-pub fn read<T>() -> Option<T>
+/// Reads the state of the first display in `displays` that has one
+pub fn read<T>(displays: &[String]) -> Option<T>
 where
     T: Sized + StateFileName + TryFrom<String>,
     <T as std::convert::TryFrom<std::string::String>>::Error: std::fmt::Debug,
 {
-    let path = get_data_path().join(T::name());
-
-    if let Ok(content) = std::fs::read(path) {
-        let string = String::from_utf8(content).unwrap();
-        return Some(string.try_into().unwrap());
+    for path in file_paths::<T>(displays) {
+        if let Ok(content) = std::fs::read(path) {
+            let string = String::from_utf8(content).unwrap();
+            return Some(string.try_into().unwrap());
+        }
     }
 
     None
 }
 
-pub fn delete<T>() -> Result<()>
+pub fn delete<T>(displays: &[String]) -> Result<()>
 where
     T: Display + StateFileName,
 {
-    let path = get_data_path().join(T::name());
-    std::fs::remove_file(path)?;
-    Ok(())
+    if !file_paths::<T>(displays).iter().any(|path| path.exists()) {
+        anyhow::bail!("No {} state to delete", T::name());
+    }
+    clear::<T>(displays)
 }
 
-pub fn file_path<T>() -> PathBuf
+/// Deletes the state files if there are any
+pub fn clear<T>(displays: &[String]) -> Result<()>
 where
     T: Display + StateFileName,
 {
-    get_data_path().join(T::name())
+    for path in file_paths::<T>(displays) {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+fn file_paths<T>(displays: &[String]) -> Vec<PathBuf>
+where
+    T: StateFileName,
+{
+    get_state_paths(displays)
+        .into_iter()
+        .map(|dir| dir.join(T::name()))
+        .collect()
 }
@@ -11,6 +11,19 @@ pub fn get_data_path() -> PathBuf {
     cache_dir.join("bluegone")
 }
 
+/// Every selected display keeps its state in a directory of its own, a daemon managing `:0`
+/// and `:1` writes to both so it's found through either. Just the data path when the displays
+/// come from the environment.
+pub fn get_state_paths(displays: &[String]) -> Vec<PathBuf> {
+    match displays {
+        [] => vec![get_data_path()],
+        displays => displays
+            .iter()
+            .map(|display| get_data_path().join("displays").join(display.replace('/', "_")))
+            .collect(),
+    }
+}
+
 pub fn new_log_file() -> Result<File> {
     let timestamp = chrono::Local::now().to_rfc3339();
     let filename = format!("bluegone-log-{}.txt", timestamp);